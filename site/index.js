@@ -1,4 +1,4 @@
-import { Universe, CellType } from "wasm-sand-sim";
+import { Universe, CellType, FlowDirection, all_elements, element_name } from "wasm-sand-sim";
 import fps_logger from "./measure_fps.js"
 // We can directly access WebAssembly's linear memory via memory
 import { memory } from "../pkg/wasm_sand_sim_bg.wasm";
@@ -9,7 +9,31 @@ const GRID_COLOR = "#D1D1D1"; // Light gray for grid lines
 const DEAD_COLOR = "#FFFFFF"; // White for empty cells
 const SAND_COLOR = "#F4A460"; // Sandy brown for sand cells
 const WATER_COLOR = "#87CEEB"; // Light blue for water cells
+const RIPPLE_COLOR = "#B0E0F0"; // Lighter blue for water flowing sideways
 const ROCK_COLOR = '#A9A9A9'; // Dark gray for rock cells
+const QUICKSAND_COLOR = '#C2A66B'; // Muddy tan for quicksand cells
+const VINE_COLOR = '#228B22'; // Forest green for vine cells
+const GEYSER_COLOR = '#5C4033'; // Dark brown for geyser cells
+const TAR_COLOR = '#1C1C1C'; // Near black for tar cells
+const SLIME_COLOR = '#7FFF00'; // Bright green for slime cells
+const NITRO_COLOR = '#E6E68A'; // Pale yellow for nitroglycerin cells
+const DUST_COLOR = '#D2C8B4'; // Pale beige for dust cells
+const MAGNET_COLOR = '#B22222'; // Red for magnet cells
+const IRON_FILINGS_COLOR = '#4A4A4A'; // Charcoal for iron filing cells
+const CLOUD_COLOR = '#DCDCE6'; // Pale gray for cloud cells
+const FROST_COLOR = '#E0FFFF'; // Near white cyan for frost cells
+const ICE_COLOR = '#A5F2F3'; // Pale cyan for ice cells
+const METHANE_COLOR = '#C8E6C8'; // Faint green for methane cells
+const RUBBER_COLOR = '#FF69B4'; // Pink for rubber cells
+const PACKED_SNOW_COLOR = '#DDE6F0'; // Bluish white for packed snow cells
+const SNOW_COLOR = '#FFFAFA'; // Snow white for loose snow cells
+const WET_CONCRETE_COLOR = '#8C8C8C'; // Mid gray for wet concrete cells
+const OIL_COLOR = '#3B2F0B'; // Dark brown for oil cells
+const FIRE_COLOR = '#FF4500'; // Orange red for fire cells
+const STEAM_COLOR = '#E6E6E6'; // Light gray for steam cells
+const ACID_COLOR = '#B0FF1A'; // Acid green for acid cells
+const LIGHTNING_COLOR = '#FFFF66'; // Pale yellow for the flash of a lightning bolt
+const LIGHTNING_FRAMES = 6; // How many frames a bolt stays on screen
 
 const CURSOR_SIZE = 40;
 const CURSOR_BORDER_WIDTH = 4;
@@ -20,13 +44,32 @@ const cellColors = {
   [CellType.Water]: WATER_COLOR,
   [CellType.Sand]: SAND_COLOR,
   [CellType.Rock]: ROCK_COLOR,
+  [CellType.Quicksand]: QUICKSAND_COLOR,
+  [CellType.Vine]: VINE_COLOR,
+  [CellType.Geyser]: GEYSER_COLOR,
+  [CellType.Tar]: TAR_COLOR,
+  [CellType.Slime]: SLIME_COLOR,
+  [CellType.Nitro]: NITRO_COLOR,
+  [CellType.Dust]: DUST_COLOR,
+  [CellType.Magnet]: MAGNET_COLOR,
+  [CellType.IronFilings]: IRON_FILINGS_COLOR,
+  [CellType.Cloud]: CLOUD_COLOR,
+  [CellType.Frost]: FROST_COLOR,
+  [CellType.Ice]: ICE_COLOR,
+  [CellType.Methane]: METHANE_COLOR,
+  [CellType.Rubber]: RUBBER_COLOR,
+  [CellType.PackedSnow]: PACKED_SNOW_COLOR,
+  [CellType.Snow]: SNOW_COLOR,
+  [CellType.WetConcrete]: WET_CONCRETE_COLOR,
+  [CellType.Oil]: OIL_COLOR,
+  [CellType.Fire]: FIRE_COLOR,
+  [CellType.Steam]: STEAM_COLOR,
+  [CellType.Acid]: ACID_COLOR,
   // Add more cell types and colors as needed
 };
 
 // Construct the universe, and get its width and height.
-const universe = Universe.new();
-universe.set_width(64 * 4);
-universe.set_height(64 * 4);
+const universe = Universe.new_with_size(64 * 4, 64 * 4);
 const width = universe.width();
 const height = universe.height();
 
@@ -38,12 +81,14 @@ canvas.width = (CELL_SIZE + 1) * width + 1;
 
 const ctx = canvas.getContext('2d');
 const playPauseButton = document.getElementById("play-pause");
+const elementSelect = document.getElementById("element-select");
 
 let mousePos = {x: 0, y: 0}
 let mouseGridPos = {row: 0, col: 0}
 let being_held = false
 let selected_element = CellType.Sand
-let animationId = null;
+let brush_mode = "paint" // "paint", "pin" or "unpin"
+let lightning = {path: [], frames: 0}
 
 const renderLoop = () => {
   fps_logger.render();
@@ -51,15 +96,12 @@ const renderLoop = () => {
   drawBackground();
   // drawGrid();
   drawCells();
+  drawLightning();
   drawCursor(mousePos);
   
-  universe.tick();
+  universe.tick(); // Does nothing while paused, the grid keeps being drawn so painting shows up
 
-  animationId = requestAnimationFrame(renderLoop);
-};
-
-const isPaused = () => {
-  return animationId === null;
+  requestAnimationFrame(renderLoop);
 };
 
 const drawBackground = () => {
@@ -91,6 +133,10 @@ const getIndex = (row, column) => {
 };
 
 const drawCells = () => {
+  // fills() and flow_directions() allocate, which can grow the wasm memory and detach
+  // any view made before them, so the view over the cells is made last
+  const fills = universe.fills();
+  const flows = universe.flow_directions();
   const cellsPtr = universe.cells();
   const cells = new Uint8Array(memory.buffer, cellsPtr, width * height);
 
@@ -103,6 +149,12 @@ const drawCells = () => {
         continue
       }
       ctx.fillStyle = cellColors[cells[idx]];
+      // Water sliding sideways is drawn lighter so the surface ripples
+      if (flows[idx] === FlowDirection.Left || flows[idx] === FlowDirection.Right) {
+        ctx.fillStyle = RIPPLE_COLOR;
+      }
+      // Partly filled water is drawn fainter
+      ctx.globalAlpha = cells[idx] === CellType.Water ? fills[idx] / 255 : 1;
 
       ctx.fillRect(
         col * (CELL_SIZE + 1) + 1,
@@ -113,9 +165,28 @@ const drawCells = () => {
     }
   }
 
+  ctx.globalAlpha = 1;
   ctx.stroke();
 };
 
+const drawLightning = () => {
+  if (lightning.frames === 0) {
+    return
+  }
+  lightning.frames--;
+  ctx.fillStyle = LIGHTNING_COLOR;
+  for (const idx of lightning.path) {
+    const row = Math.floor(idx / width);
+    const col = idx % width;
+    ctx.fillRect(
+      col * (CELL_SIZE + 1) + 1,
+      row * (CELL_SIZE + 1) + 1,
+      CELL_SIZE,
+      CELL_SIZE
+    );
+  }
+};
+
 function drawCursor(pos) {
   ctx.strokeStyle = CURSOR_COLOR;
   ctx.lineWidth = CURSOR_BORDER_WIDTH;
@@ -134,28 +205,45 @@ function getMousePos(evt) {
 
 const play = () => {
   playPauseButton.textContent = "⏸";
-  renderLoop();
+  universe.set_paused(false);
 };
 
 const pause = () => {
   playPauseButton.textContent = "▶";
-  cancelAnimationFrame(animationId);
-  animationId = null;
+  universe.set_paused(true);
 };
 
 playPauseButton.addEventListener("click", event => {
-  if (isPaused()) {
+  if (universe.is_paused()) {
     play();
   } else {
     pause();
   }
 });
 
+// The palette is built from the elements the crate exports so it never misses one
+for (const element of all_elements()) {
+  const option = document.createElement("option");
+  option.value = element;
+  option.textContent = element_name(element);
+  elementSelect.appendChild(option);
+}
+elementSelect.value = selected_element;
+
+elementSelect.addEventListener("change", event => {
+  selected_element = Number(elementSelect.value);
+  brush_mode = "paint";
+});
+
 function setCells() {
   if (being_held) {
     const { row, col } = mouseGridPos;
     if (row >= 0 && row < height && col >= 0 && col < width) {
       const radius = CURSOR_SIZE / 8;
+      if (brush_mode === "paint") {
+        universe.paint_circle(row, col, radius, selected_element);
+        return;
+      }
       const startRow = Math.max(row - radius, 0);
       const endRow = Math.min(row + radius, height - 1);
       const startCol = Math.max(col - radius, 0);
@@ -164,7 +252,7 @@ function setCells() {
       for (let r = startRow; r <= endRow; r++) {
         for (let c = startCol; c <= endCol; c++) {
           if (Math.sqrt((r - row) ** 2 + (c - col) ** 2) <= radius) {
-            universe.set_cell(r, c, selected_element);
+            universe.set_pinned(r, c, brush_mode === "pin");
           }
         }
       }
@@ -201,6 +289,20 @@ canvas.addEventListener('mouseup', function() {
 })
 
 document.addEventListener("keydown", (event) => {
+  if (event.key === "p" || event.key === "P") {
+    brush_mode = "pin"
+    return
+  }
+  else if (event.key === "u" || event.key === "U") {
+    brush_mode = "unpin"
+    return
+  }
+  else if (event.key === "z" || event.key === "Z") {
+    lightning = {path: universe.strike_lightning(mouseGridPos.col), frames: LIGHTNING_FRAMES}
+    return
+  }
+  brush_mode = "paint"
+
   if (event.key === "s" || event.key === "S") {
     selected_element = CellType.Sand
   } 
@@ -212,14 +314,92 @@ document.addEventListener("keydown", (event) => {
     selected_element = CellType.Rock
   }
 
+  else if (event.key === "q" || event.key === "Q") {
+    selected_element = CellType.Quicksand
+  }
+
+  else if (event.key === "v" || event.key === "V") {
+    selected_element = CellType.Vine
+  }
+
+  else if (event.key === "g" || event.key === "G") {
+    selected_element = CellType.Geyser
+  }
+
+  else if (event.key === "t" || event.key === "T") {
+    selected_element = CellType.Tar
+  }
+
+  else if (event.key === "l" || event.key === "L") {
+    selected_element = CellType.Slime
+  }
+
+  else if (event.key === "n" || event.key === "N") {
+    selected_element = CellType.Nitro
+  }
+
+  else if (event.key === "d" || event.key === "D") {
+    selected_element = CellType.Dust
+  }
+
+  else if (event.key === "m" || event.key === "M") {
+    selected_element = CellType.Magnet
+  }
+
+  else if (event.key === "i" || event.key === "I") {
+    selected_element = CellType.IronFilings
+  }
+
+  else if (event.key === "c" || event.key === "C") {
+    selected_element = CellType.Cloud
+  }
+
+  else if (event.key === "f" || event.key === "F") {
+    selected_element = CellType.Frost
+  }
+
+  else if (event.key === "h" || event.key === "H") {
+    selected_element = CellType.Methane
+  }
+
+  else if (event.key === "b" || event.key === "B") {
+    selected_element = CellType.Rubber
+  }
+
+  else if (event.key === "k" || event.key === "K") {
+    selected_element = CellType.PackedSnow
+  }
+
+  else if (event.key === "o" || event.key === "O") {
+    selected_element = CellType.Snow
+  }
+
+  else if (event.key === "x" || event.key === "X") {
+    selected_element = CellType.WetConcrete
+  }
+
+  else if (event.key === "y" || event.key === "Y") {
+    selected_element = CellType.Oil
+  }
+
+  else if (event.key === "a" || event.key === "A") {
+    selected_element = CellType.Fire
+  }
+
+  else if (event.key === "j" || event.key === "J") {
+    selected_element = CellType.Steam
+  }
+
   else if (event.key === "e" || event.key === "E") {
     selected_element = CellType.Dead
   }
-  
+
+  elementSelect.value = selected_element;
 });
 
 // ------------ executes once
 drawGrid();
 drawCells();
 // ------------
-play();
\ No newline at end of file
+play();
+renderLoop();
\ No newline at end of file
@@ -1,11 +1,88 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
-use web_sys::js_sys::Math::random;
+mod font;
 mod utils;
+use utils::Rng;
 
 
+const OUT_OF_BOUNDS: u8 = 255; // Stands in for cells outside the grid in id buffers
 const WIDTH: u32 = 64;
+const MAX_CELLS: u32 = 1 << 24; // Largest grid allowed, about 4096 by 4096
 const HEIGHT: u32 = 64;
 const SPREAD_FACTOR: u32 = 3;
+const VINE_GROWTH_CHANCE: f64 = 0.05; // Chance per tick that a vine cell sprouts a new one
+const VINE_MAX_LENGTH: u32 = 40; // How many cells a vine can grow away from where it was planted
+const GEYSER_DORMANT_TICKS: u32 = 120; // How long a geyser stays quiet between eruptions
+const GEYSER_ERUPTION_TICKS: u32 = 30; // How long an eruption lasts
+const GEYSER_JET_HEIGHT: u32 = 6; // How far above the geyser water is thrown
+const TAR_FLOW_CHANCE: f64 = 0.1; // Tar is so viscous it only moves on some ticks
+const TAR_SINK_CHANCE: f64 = 0.1; // Chance per tick that a cell caught in tar sinks one cell deeper
+const CONCRETE_FLOW_CHANCE: f64 = 0.3; // Wet concrete is thick and only moves on some ticks
+const CONCRETE_CURE_TICKS: u32 = 120; // Ticks wet concrete has to rest before it sets into rock
+const FIRE_LIFETIME: u32 = 20; // Ticks a fire cell burns before it goes out
+const BOIL_CHANCE: f64 = 0.5; // Chance per tick that water at the boiling point or touching fire turns into steam
+const STEAM_LIFETIME: u32 = 60; // Ticks steam rises before it condenses or disappears
+const STEAM_CONDENSE_CHANCE: f64 = 0.5; // Chance that steam turns back into water instead of disappearing
+const ACID_DISSOLVE_CHANCE: f64 = 0.1; // Chance per tick that acid eats a sand or rock cell it touches
+const SLIME_JIGGLE_CHANCE: f64 = 0.3; // Chance per tick that a resting slime cell shuffles around its blob
+const PRESSURE_CHANCE: f64 = 0.2; // Chance per tick that resting surface water looks for a lower surface to move to
+const PRESSURE_REACH: i64 = 16; // How far sideways that search goes through the body of water
+const PRESSURE_DEPTH: i64 = 32; // How far down that search goes
+const MAX_PRESSURE_CELLS: usize = 256; // Water cells the search visits before giving up
+const SETTLE_QUIET_PASSES: u32 = 32; // Passes in a row without movement before `settle_liquids` calls the liquids settled
+const MAX_FILL: u8 = 255; // Fill level of a completely full water cell
+const MIN_SPLIT_FILL: u8 = 64; // Water never splits into cells holding less than this
+const DEFAULT_SURFACE_TENSION: f64 = 0.9; // Chance per tick that a lone droplet keeps from spreading
+const DROPLET_MAX_NEIGHBORS: usize = 1; // Water with more water neighbours than this spreads normally
+const BOUNCE_MIN_ENERGY: u32 = 4; // Slower cells just come to rest on rubber
+const DEFAULT_RUBBER_RESTITUTION: f64 = 0.6; // Share of a cell's energy that turns into bounce height
+const ROCK_IMPACT_MIN_ENERGY: u32 = 4; // Slower cells landing on rock don't damage it
+const DEFAULT_PACKED_SNOW_STRENGTH: u32 = 6; // Impact energy that breaks packed snow
+const SNOW_CRUMBLE_RADIUS: i64 = 3; // How far from a hit packed snow breaks into loose snow
+const BLAST_ROCK_DAMAGE: u32 = 20; // Damage an explosion does to rock caught in it
+const NITRO_IMPACT_ENERGY: u32 = 6; // Fall energy at which an impact sets off nitroglycerin
+const NITRO_BLAST_RADIUS: u32 = 4;
+const EROSION_MIN_ENERGY: u32 = 3; // Water has to be moving at least this fast to carry sand away
+const DEFAULT_EROSION_RATE: f64 = 0.05;
+const DUST_FALL_CHANCE: f64 = 0.1; // Dust floats, only falling on some ticks
+const DUST_DRIFT_CHANCE: f64 = 0.2;
+const DUST_DISTURB_ENERGY: u32 = 4; // Cells moving this fast nearby kick dust back up
+const DUST_DISTURB_RADIUS: u32 = 2;
+const MAGNET_RADIUS: u32 = 8; // How far away a magnet attracts iron filings
+const MAGNET_STRENGTH: f64 = 0.8; // Chance per tick that a filing in range is pulled instead of falling
+const QUICKSAND_SINK_CHANCE: f64 = 0.1; // Chance per tick that quicksand pulls a resting solid one cell down
+const DEFAULT_CLOUD_DRIFT_RATE: f64 = 0.2; // Chance per tick that a cloud moves one cell
+const DEFAULT_CLOUD_RAIN_RATE: f64 = 0.02; // Chance per tick that a cloud drops water below it
+const FROST_SPREAD_CHANCE: f64 = 0.05; // Chance per tick that frost freezes a neighbouring water cell
+const FROST_MAX_SPREAD: u32 = 20; // How many cells frost can crawl away from where it was placed
+const MAX_ACTIVITY: u8 = 255; // Activity of a cell that moved this tick
+const DEFAULT_ACTIVITY_DECAY: u8 = 16; // How much activity fades every tick
+const MAX_HUMIDITY: u8 = 255; // Humidity of water itself
+const HUMIDITY_SPREAD_LOSS: u8 = 32; // How much drier each cell is than its wettest neighbour
+const HUMIDITY_DECAY: u8 = 4; // How much a cell dries out every tick
+const WET_SAND_HUMIDITY: u8 = 128; // Sand at least this wet clumps instead of sliding
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+const BRUSH_RADIUS: i64 = 3; // Size of the shapes `Universe::stamp` paints
+const SPRAY_DENSITY: f64 = 0.3; // Share of the cells under a spray brush that get painted
+const MAX_QUEUED_EVENTS: usize = 1024; // Events past this are dropped until javascript drains the queue
+const DEFAULT_LIGHTNING_JAGGEDNESS: f64 = 0.5; // Chance per row that a bolt steps sideways
+const DEFAULT_LIGHTNING_BRANCHING: f64 = 0.05; // Chance per row that a bolt forks
+const MAX_LIGHTNING_BRANCHES: u32 = 4;
+const DEFAULT_OSCILLATION_LIMIT: u32 = 0; // Off, tracking costs a map lookup on every swap
+const MAX_TRACKED_SWAPS: usize = 4096; // Swaps past this in one pass aren't checked for oscillation
+const BASE_LAYER: &str = "base"; // Name of the original grid once layers are added
+const CHUNK_SIZE: u32 = 16; // Side of the square chunks `tick()` skips when nothing in them can change
+const AMBIENT_TEMPERATURE: f32 = 20.0; // What every cell starts at and slowly returns to
+const FIRE_TEMPERATURE: f32 = 800.0; // Fire holds the cell it burns in at this temperature
+const BOILING_POINT: f32 = 100.0; // Water never gets hotter than this, it boils off instead
+const MIN_TEMPERATURE: f32 = -273.15; // Coldest `add_heat` can make a cell, absolute zero
+const MAX_TEMPERATURE: f32 = 10_000.0; // Hottest `add_heat` can make a cell
+const HEAT_DIFFUSION_RATE: f32 = 0.2; // Share of their difference two fully conducting neighbours even out per tick
+const HEAT_LOSS_RATE: f32 = 0.02; // Share of its difference to the ambient temperature a cell loses per tick
+const SAVE_HEADER_BYTES: usize = 9; // Width, height and the update stamp of a `Universe::serialize` buffer
+const SAVE_CELL_BYTES: usize = 13; // Bytes every cell takes up after the header
 
 /// Javascript can only store C style enums memory buffer
 #[wasm_bindgen]
@@ -16,6 +93,365 @@ pub enum CellType {
     Sand = 1,
     Water = 2,
     Rock = 3,
+    Quicksand = 4,
+    Vine = 5,
+    Geyser = 6,
+    Tar = 7,
+    Slime = 8,
+    Nitro = 9,
+    Dust = 10,
+    Magnet = 11,
+    IronFilings = 12,
+    Cloud = 13,
+    Frost = 14,
+    Ice = 15,
+    Methane = 16,
+    Rubber = 17,
+    PackedSnow = 18,
+    Snow = 19,
+    WetConcrete = 20,
+    Oil = 21,
+    Fire = 22,
+    Steam = 23,
+    Acid = 24,
+}
+
+/// Every element the user can paint, in the order a palette should list them
+#[wasm_bindgen]
+pub fn all_elements() -> Vec<CellType> {
+    vec![
+        CellType::Dead,
+        CellType::Sand,
+        CellType::Water,
+        CellType::Rock,
+        CellType::Quicksand,
+        CellType::Vine,
+        CellType::Geyser,
+        CellType::Tar,
+        CellType::Slime,
+        CellType::Nitro,
+        CellType::Dust,
+        CellType::Magnet,
+        CellType::IronFilings,
+        CellType::Cloud,
+        CellType::Frost,
+        CellType::Ice,
+        CellType::Methane,
+        CellType::Rubber,
+        CellType::PackedSnow,
+        CellType::Snow,
+        CellType::WetConcrete,
+        CellType::Oil,
+        CellType::Fire,
+        CellType::Steam,
+        CellType::Acid,
+    ]
+}
+
+/// Display name of an element for UI menus
+#[wasm_bindgen]
+pub fn element_name(ct: CellType) -> String {
+    let name = match ct {
+        CellType::Dead => "Eraser",
+        CellType::Sand => "Sand",
+        CellType::Water => "Water",
+        CellType::Rock => "Rock",
+        CellType::Quicksand => "Quicksand",
+        CellType::Vine => "Vine",
+        CellType::Geyser => "Geyser",
+        CellType::Tar => "Tar",
+        CellType::Slime => "Slime",
+        CellType::Nitro => "Nitroglycerin",
+        CellType::Dust => "Dust",
+        CellType::Magnet => "Magnet",
+        CellType::IronFilings => "Iron Filings",
+        CellType::Cloud => "Cloud",
+        CellType::Frost => "Frost",
+        CellType::Ice => "Ice",
+        CellType::Methane => "Methane",
+        CellType::Rubber => "Rubber",
+        CellType::PackedSnow => "Packed Snow",
+        CellType::Snow => "Snow",
+        CellType::WetConcrete => "Wet Concrete",
+        CellType::Oil => "Oil",
+        CellType::Fire => "Fire",
+        CellType::Steam => "Steam",
+        CellType::Acid => "Acid",
+    };
+    name.to_string()
+}
+
+impl CellType {
+    /// Single character used for this element in run-length encoded grids
+    fn symbol(self) -> char {
+        match self {
+            CellType::Dead => '.',
+            CellType::Sand => 's',
+            CellType::Water => 'w',
+            CellType::Rock => 'r',
+            CellType::Quicksand => 'q',
+            CellType::Vine => 'v',
+            CellType::Geyser => 'g',
+            CellType::Tar => 't',
+            CellType::Slime => 'l',
+            CellType::Nitro => 'n',
+            CellType::Dust => 'd',
+            CellType::Magnet => 'm',
+            CellType::IronFilings => 'i',
+            CellType::Cloud => 'c',
+            CellType::Frost => 'f',
+            CellType::Ice => 'e',
+            CellType::Methane => 'h',
+            CellType::Rubber => 'b',
+            CellType::PackedSnow => 'k',
+            CellType::Snow => 'o',
+            CellType::WetConcrete => 'x',
+            CellType::Oil => 'y',
+            CellType::Fire => 'a',
+            CellType::Steam => 'j',
+            CellType::Acid => 'u',
+        }
+    }
+
+    fn from_symbol(symbol: char) -> Option<CellType> {
+        all_elements().into_iter().find(|ct| ct.symbol() == symbol)
+    }
+
+    /// RGBA color `Universe::render_to_rgba` draws this element in, the same palette the
+    /// site uses. Empty cells are transparent so the page background shows through.
+    fn color(self) -> [u8; 4] {
+        match self {
+            CellType::Dead => [0, 0, 0, 0],
+            CellType::Sand => [0xF4, 0xA4, 0x60, 0xFF],
+            CellType::Water => [0x87, 0xCE, 0xEB, 0xFF],
+            CellType::Rock => [0xA9, 0xA9, 0xA9, 0xFF],
+            CellType::Quicksand => [0xC2, 0xA6, 0x6B, 0xFF],
+            CellType::Vine => [0x22, 0x8B, 0x22, 0xFF],
+            CellType::Geyser => [0x5C, 0x40, 0x33, 0xFF],
+            CellType::Tar => [0x1C, 0x1C, 0x1C, 0xFF],
+            CellType::Slime => [0x7F, 0xFF, 0x00, 0xFF],
+            CellType::Nitro => [0xE6, 0xE6, 0x8A, 0xFF],
+            CellType::Dust => [0xD2, 0xC8, 0xB4, 0xFF],
+            CellType::Magnet => [0xB2, 0x22, 0x22, 0xFF],
+            CellType::IronFilings => [0x4A, 0x4A, 0x4A, 0xFF],
+            CellType::Cloud => [0xDC, 0xDC, 0xE6, 0xFF],
+            CellType::Frost => [0xE0, 0xFF, 0xFF, 0xFF],
+            CellType::Ice => [0xA5, 0xF2, 0xF3, 0xFF],
+            CellType::Methane => [0xC8, 0xE6, 0xC8, 0xFF],
+            CellType::Rubber => [0xFF, 0x69, 0xB4, 0xFF],
+            CellType::PackedSnow => [0xDD, 0xE6, 0xF0, 0xFF],
+            CellType::Snow => [0xFF, 0xFA, 0xFA, 0xFF],
+            CellType::WetConcrete => [0x8C, 0x8C, 0x8C, 0xFF],
+            CellType::Oil => [0x3B, 0x2F, 0x0B, 0xFF],
+            CellType::Fire => [0xFF, 0x45, 0x00, 0xFF],
+            CellType::Steam => [0xE6, 0xE6, 0xE6, 0xFF],
+            CellType::Acid => [0xB0, 0xFF, 0x1A, 0xFF],
+        }
+    }
+}
+
+/// Order in which `tick()` visits the columns of each row
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanMode {
+    Ordered = 0,  // Left to right and right to left on alternating ticks
+    Shuffled = 1, // A fresh random permutation per row, reduces diagonal streaking
+}
+
+/// Which way falling sand and liquids are pulled, see `Universe::set_gravity`
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GravityDir {
+    Down = 0,
+    Up = 1,
+    Left = 2,
+    Right = 3,
+}
+
+/// Which way a cell moved, matching the branches of the update functions
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveKind {
+    Down = 0,
+    SideDown = 1,
+    Side = 2,
+    Up = 3,
+}
+
+/// One move recorded by `Universe::tick_probe`
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProbeRecord {
+    pub from_row: u32,
+    pub from_col: u32,
+    pub to_row: u32,
+    pub to_col: u32,
+    pub kind: MoveKind,
+}
+
+/// Shapes `Universe::stamp` can paint, all centered on the stamped position
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Brush {
+    Dot = 0,   // A filled disc
+    Line = 1,  // A horizontal line
+    Spray = 2, // Random cells scattered over a disc
+    Ring = 3,  // The outline of a disc
+}
+
+impl Brush {
+    /// Offsets from the center of every cell the brush covers
+    fn offsets(self, rng: &Rng) -> Vec<(i64, i64)> {
+        let radius = BRUSH_RADIUS;
+        let disc = (-radius..=radius)
+            .flat_map(|dr| (-radius..=radius).map(move |dc| (dr, dc)))
+            .filter(move |&(dr, dc)| dr * dr + dc * dc <= radius * radius);
+        match self {
+            Brush::Dot => disc.collect(),
+            Brush::Line => (-radius..=radius).map(|dc| (0, dc)).collect(),
+            Brush::Spray => disc.filter(|_| rng.next_f64() < SPRAY_DENSITY).collect(),
+            Brush::Ring => disc
+                .filter(|&(dr, dc)| dr * dr + dc * dc > (radius - 1) * (radius - 1))
+                .collect(),
+        }
+    }
+}
+
+/// Which way a water cell moved during its last update, for drawing ripples
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowDirection {
+    Still = 0,
+    Down = 1,
+    DownLeft = 2,
+    DownRight = 3,
+    Left = 4,
+    Right = 5,
+    Up = 6,
+}
+
+impl FlowDirection {
+    fn from_u8(value: u8) -> Option<FlowDirection> {
+        let flow = match value {
+            0 => FlowDirection::Still,
+            1 => FlowDirection::Down,
+            2 => FlowDirection::DownLeft,
+            3 => FlowDirection::DownRight,
+            4 => FlowDirection::Left,
+            5 => FlowDirection::Right,
+            6 => FlowDirection::Up,
+            _ => return None,
+        };
+        Some(flow)
+    }
+}
+
+/// Something worth a sound or a particle effect that happened during a tick
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    Explosion = 0,
+    Eruption = 1,  // A geyser started throwing water
+    Swallowed = 2, // Quicksand pulled a cell down into it
+    FlashBurn = 3, // A pocket of methane went up all at once
+    Lightning = 4, // A bolt hit the ground or something standing on it
+    Splash = 5,    // Enough liquid landed in one tick, see `Universe::set_sound_threshold`
+    Rumble = 6,    // Explosions cleared enough cells in one tick
+}
+
+/// Amounts `tick()` adds up so javascript can play a sound once they cross a threshold
+#[wasm_bindgen]
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoundKind {
+    Splash = 0, // Liquid cells that stopped falling
+    Rumble = 1, // Cells cleared by explosions
+}
+
+/// One event queued by `tick()`, drained with `Universe::take_events`
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub row: u32,
+    pub col: u32,
+}
+
+/// What `Universe::inspect` found at a single position
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Inspection {
+    pub cell: Cell,
+    pub was_updated: bool, // Counts as updated this pass, between ticks only cells held still by `track_swap` do
+    pub temperature: f32, // Of the position rather than the cell, like `Universe::get_temperature`
+}
+
+/// A rule added with `Universe::add_reaction`
+#[derive(Clone, Copy)]
+struct Reaction {
+    a: CellType,
+    b: CellType,
+    out_a: CellType,
+    out_b: CellType,
+    probability: f64,
+}
+
+/// A well added with `Universe::add_gravity_well`
+#[derive(Clone, Copy)]
+struct GravityWell {
+    row: u32,
+    col: u32,
+    radius: u32,
+    strength: f64,
+}
+
+/// How much of a `SoundKind` happened since the last tick ended, and where it last did
+#[derive(Clone, Copy, Default)]
+struct SoundMeter {
+    threshold: u32, // 0 never makes a sound
+    level: u32,
+    row: u32,
+    col: u32,
+}
+
+/// A regulator added with `Universe::maintain_level`, the columns are inclusive
+#[derive(Clone, Copy)]
+struct LevelRegulator {
+    col_start: u32,
+    col_end: u32,
+    target_row: u32,
+    cell_type: CellType,
+    flow_rate: u32,
+}
+
+/// How many passes in a row the same two cells have swapped back and forth between a pair
+/// of positions
+#[derive(Clone, Copy)]
+struct SwapStreak {
+    last_pass: u64,
+    passes: u32,
+    after: (CellType, CellType), // What the lower and higher position held after the last swap
+}
+
+/// A layer added with `Universe::add_layer`. The active layer's cells live in
+/// `Universe::cells` while it is active, leaving its own `cells` empty.
+struct Layer {
+    name: String,
+    cells: Vec<Cell>,
+    temperature: Vec<f32>,
+    swap_streaks: HashMap<(usize, usize), SwapStreak>,
+    update_stamp: u8,
+}
+
+/// Amount of every element at the start of the last tick, and how much reactions were
+/// allowed to change it. Indexed by `CellType as usize`.
+#[cfg(feature = "conservation-check")]
+struct Conservation {
+    amounts: Vec<i64>,
+    sanctioned: Vec<i64>,
+    conserved: bool,
 }
 
 #[derive(PartialEq, Eq)]
@@ -23,44 +459,182 @@ enum Phase {
     Dead,
     Solid,
     Liquid,
+    Gas,
     Immovable
 }
 
+#[wasm_bindgen]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Cell {
     id: CellType,
     energy: u32,
-    has_been_updated: bool,
+    update_stamp: u8, // The cell counts as updated while this matches the universe's
+    fill: u8, // How much of the cell the water occupies, total fill is conserved
+    pinned: bool, // Pinned cells never move and can't be displaced, filled or swallowed
+    flow: FlowDirection, // Only tracked for water
+    activity: u8, // Jumps up when something moves here and fades every tick
+    humidity: u8, // Only solids soak up water, everything else stays dry
+    motion: (i8, i8), // Columns and rows the cell moved during the last tick
+    #[cfg(feature = "particle-ids")]
+    particle: u32, // Follows the cell as it moves, 0 until the universe labels it
 }
 
 impl Cell {
     fn set_cell(&mut self, ct: CellType) {
         self.id = ct;
+        self.energy = Cell::new(ct).energy;
+        self.fill = Cell::new(ct).fill;
+        self.flow = FlowDirection::Still;
+        self.humidity = 0;
+        #[cfg(feature = "particle-ids")]
+        {
+            self.particle = 0;
+        }
     }
 
     fn new(ct: CellType) -> Self {
-        return Cell {
+        Cell {
             id: ct,
-            energy: 0,
-            has_been_updated: false,
-        };
+            energy: match ct {
+                CellType::Fire => FIRE_LIFETIME,
+                CellType::Steam => STEAM_LIFETIME,
+                _ => 0,
+            },
+            update_stamp: 0,
+            fill: if ct == CellType::Dead { 0 } else { MAX_FILL },
+            pinned: false,
+            flow: FlowDirection::Still,
+            activity: 0,
+            humidity: 0,
+            motion: (0, 0),
+            #[cfg(feature = "particle-ids")]
+            particle: 0,
+        }
     }
 
     fn phase(&self) -> Phase {
-        let id_as_num = self.id as u8;
-        if id_as_num == 0 {
-            return Phase::Dead;
+        match self.id {
+            CellType::Dead => Phase::Dead,
+            CellType::Sand
+            | CellType::Quicksand
+            | CellType::Dust
+            | CellType::IronFilings
+            | CellType::Snow => Phase::Solid,
+            CellType::Water
+            | CellType::Tar
+            | CellType::Slime
+            | CellType::Nitro
+            | CellType::WetConcrete
+            | CellType::Oil
+            | CellType::Acid => Phase::Liquid,
+            CellType::Cloud | CellType::Methane | CellType::Fire | CellType::Steam => Phase::Gas,
+            CellType::Rock
+            | CellType::Vine
+            | CellType::Geyser
+            | CellType::Magnet
+            | CellType::Frost
+            | CellType::Ice
+            | CellType::Rubber
+            | CellType::PackedSnow => Phase::Immovable,
+        }
+    }
+
+    /// Whether updating the cell is guaranteed to do nothing, not even draw a random number
+    fn is_inert(&self) -> bool {
+        self.pinned
+            || matches!(
+                self.id,
+                CellType::Dead
+                    | CellType::Rock
+                    | CellType::Magnet
+                    | CellType::Ice
+                    | CellType::Rubber
+                    | CellType::PackedSnow
+            )
+    }
+
+    /// How readily heat passes through the cell, from 0 to 1
+    fn conductivity(&self) -> f32 {
+        match self.id {
+            CellType::Dead | CellType::Rubber => 0.05,
+            CellType::Cloud | CellType::Methane | CellType::Steam | CellType::Dust => 0.1,
+            CellType::Oil | CellType::Tar | CellType::Vine | CellType::Snow | CellType::PackedSnow => 0.15,
+            CellType::Sand | CellType::Quicksand | CellType::Slime | CellType::Nitro => 0.2,
+            CellType::Frost | CellType::Ice | CellType::WetConcrete | CellType::Acid => 0.3,
+            CellType::Rock | CellType::Geyser => 0.4,
+            CellType::Fire => 0.5,
+            CellType::Water => 0.6,
+            CellType::Magnet | CellType::IronFilings => 1.0,
         }
-        if id_as_num < 2 {
-            return Phase::Solid;
+    }
+
+    /// Heavier cells sink through lighter ones
+    fn density(&self) -> u32 {
+        match self.id {
+            CellType::Dead | CellType::Cloud | CellType::Methane | CellType::Fire | CellType::Steam => 0,
+            CellType::Dust => 1,
+            CellType::Snow => 5,
+            CellType::Oil => 8,
+            CellType::Water => 10,
+            CellType::Slime | CellType::Acid => 11,
+            CellType::Tar => 12,
+            CellType::Quicksand => 15,
+            CellType::Sand => 20,
+            CellType::WetConcrete => 24,
+            CellType::Nitro => 25,
+            CellType::IronFilings => 35,
+            CellType::Rock
+            | CellType::Vine
+            | CellType::Geyser
+            | CellType::Magnet
+            | CellType::Frost
+            | CellType::Ice
+            | CellType::Rubber
+            | CellType::PackedSnow => 30,
         }
+    }
 
-        if id_as_num < 3 {
-            return Phase::Liquid;
+    /// Chance per tick that a touching flame sets the cell alight. Methane isn't listed,
+    /// a flame burns a whole pocket of it at once instead.
+    fn flammability(&self) -> f64 {
+        match self.id {
+            CellType::Oil | CellType::Dust => 1.0,
+            CellType::Vine => 0.5,
+            CellType::Tar => 0.1, // Thick enough that it only smoulders along
+            _ => 0.0,
         }
+    }
+}
+
+/// Read-only accessors so javascript can inspect a single cell
+#[wasm_bindgen]
+impl Cell {
+    pub fn id(&self) -> CellType {
+        self.id
+    }
+
+    pub fn energy(&self) -> u32 {
+        self.energy
+    }
+
+    pub fn fill(&self) -> u8 {
+        self.fill
+    }
+
+    pub fn pinned(&self) -> bool {
+        self.pinned
+    }
 
-        return Phase::Immovable
+    pub fn flow(&self) -> FlowDirection {
+        self.flow
+    }
+
+    pub fn activity(&self) -> u8 {
+        self.activity
+    }
 
+    pub fn humidity(&self) -> u8 {
+        self.humidity
     }
 }
 
@@ -69,6 +643,53 @@ pub struct Universe {
     width: u32,
     height: u32,
     cells: Vec<Cell>,
+    id_buffer: Vec<CellType>, // What `cells()` hands javascript a pointer into
+    energy_buffer: Vec<u32>, // What `energies()` hands javascript a pointer into
+    temperature: Vec<f32>, // Of every position rather than every cell, heat doesn't move with cells
+    scan_mode: ScanMode,
+    chunked: bool, // Skip chunks where nothing can happen instead of scanning every cell
+    max_fall_distance: u32,
+    terminal_velocity: u32, // Energy a falling cell stops gaining energy at
+    slide_reach: u32, // How far down a slope sand can slide diagonally in one tick
+    rock_durability: u32, // Damage rock takes before crumbling into sand, 0 makes it indestructible
+    packed_snow_strength: u32,
+    rubber_restitution: f64,
+    update_intervals: Vec<u32>, // Every how many ticks each element updates, indexed by `CellType`
+    generation: u64, // Number of ticks run so far
+    paused: bool, // A paused universe ignores `tick()` but can still be painted and read
+    erosion_rate: f64, // Chance per tick that fast water next to sand dislodges a grain
+    surface_tension: f64,
+    cloud_drift_rate: f64,
+    cloud_rain_rate: f64,
+    probe: Option<Vec<ProbeRecord>>, // Only recording while `tick_probe` runs
+    side_bias: f64, // Chance that a cell tries to move right before left
+    activity_decay: u8,
+    events: Vec<Event>, // Queued during ticks until `take_events` drains them
+    sound_meters: Vec<SoundMeter>, // Indexed by `SoundKind`
+    reactions: Vec<Reaction>,
+    gravity_wells: Vec<GravityWell>,
+    level_regulators: Vec<LevelRegulator>,
+    oscillation_limit: u32, // 0 turns oscillation detection off
+    swap_streaks: HashMap<(usize, usize), SwapStreak>, // Keyed by the pair of indices, lowest first
+    held: Vec<usize>, // Cells that skip the next pass because they kept swapping back and forth
+    pass: u64, // Scan passes run so far, counting both ticks and `settle_liquids` passes
+    update_stamp: u8, // Cells updated during the current pass are marked with it, never 0
+    layers: Vec<Layer>, // Empty until a layer is added, the grid is then the first layer
+    density_aware_paint: bool,
+    active_layer: usize,
+    rain_rate: f64, // Cells spawned along the top row per tick, 0 when it isn't raining
+    rain_type: CellType,
+    active_bounds: Option<(u32, u32, u32, u32)>, // Inclusive rows and columns `tick()` simulates
+    lightning_jaggedness: f64,
+    lightning_branching: f64,
+    rng: Rng, // Every random number the simulation draws comes from here
+    wrap: bool, // Cells leaving one edge of the grid come back in at the opposite one
+    gravity: GravityDir,
+    tick_parity: bool, // Flips every tick, ordered scans go right to left while it is set
+    #[cfg(feature = "conservation-check")]
+    conservation: Conservation,
+    #[cfg(feature = "particle-ids")]
+    next_particle: u32,
 }
 
 
@@ -77,33 +698,204 @@ impl Universe {
         (row * self.width + column) as usize
     }
 
-    fn is_empty_and_inbound(&self, row: u32, col: u32) -> Option<(u32, u32)> {
-        if !(row < self.height && col < self.width) {
-            return None; // This also works for -1 which gets converted to u32MAX
+    /// Keeps a grid dimension at least 1 and small enough that the grid stays within
+    /// `MAX_CELLS` given the other dimension
+    fn clamp_dimension(size: u32, other: u32) -> u32 {
+        size.clamp(1, (MAX_CELLS / other.max(1)).max(1))
+    }
+
+    /// Marks every chunk next to a lively one as active too, as a margin around them. What
+    /// keeps skipping the rest exact is that they hold nothing but inert cells, and that a
+    /// cell moved or spawned into one of them during the tick is stamped as updated, so a
+    /// full scan would pass over it as well. The next tick finds it lively and wakes its
+    /// chunk, however far it travelled.
+    fn spread_chunks(lively: &[bool], chunks_wide: usize, chunks_high: usize) -> Vec<bool> {
+        let mut active = vec![false; lively.len()];
+        for chunk_row in 0..chunks_high {
+            for chunk_col in 0..chunks_wide {
+                if !lively[chunk_row * chunks_wide + chunk_col] {
+                    continue;
+                }
+                for r in chunk_row.saturating_sub(1)..(chunk_row + 2).min(chunks_high) {
+                    for c in chunk_col.saturating_sub(1)..(chunk_col + 2).min(chunks_wide) {
+                        active[r * chunks_wide + c] = true;
+                    }
+                }
+            }
+        }
+        active
+    }
+
+    /// Whether a position is inside the grid and inside the active bounds, if any. Cells
+    /// can't move to positions outside of them, so nothing leaks out of the window.
+    fn is_simulated(&self, row: u32, col: u32) -> bool {
+        let in_grid = row < self.height && col < self.width;
+        in_grid
+            && match self.active_bounds {
+                Some((r0, c0, r1, c1)) => (r0..=r1).contains(&row) && (c0..=c1).contains(&col),
+                None => true,
+            }
+    }
+
+    /// Moves a coordinate that went a little past the edge of a grid dimension back in
+    /// from the other side. Coordinates are offsets from a cell inside the grid, so one
+    /// that went below 0 wrapped around to near `u32::MAX` and is read as negative here.
+    fn wrap_coordinate(coordinate: u32, size: u32) -> u32 {
+        (coordinate as i32 as i64).rem_euclid(size as i64) as u32
+    }
+
+    /// Brings a position past an edge back in on the other side in wrap mode, otherwise
+    /// leaves it as it is
+    fn wrap_position(&self, row: u32, col: u32) -> (u32, u32) {
+        if self.wrap {
+            (Universe::wrap_coordinate(row, self.height), Universe::wrap_coordinate(col, self.width))
+        } else {
+            (row, col) // -1 was converted to u32::MAX, which is off the grid as well
+        }
+    }
+
+    /// The position a cell moving to (row, col) ends up at, or None if it can't go there.
+    /// In wrap mode positions past an edge come back in on the other side, otherwise they
+    /// are off the grid.
+    fn resolve_position(&self, row: u32, col: u32) -> Option<(u32, u32)> {
+        let (row, col) = self.wrap_position(row, col);
+        if self.is_simulated(row, col) {
+            Some((row, col))
+        } else {
+            None
         }
+    }
+
+    fn is_empty_and_inbound(&self, row: u32, col: u32) -> Option<(u32, u32)> {
+        let (row, col) = self.resolve_position(row, col)?;
         let idx = self.get_index(row, col);
-        if self.cells[idx].id == CellType::Dead {
+        if self.cells[idx].id == CellType::Dead && !self.cells[idx].pinned {
             return Some((row, col));
         }
         None
     }
 
     fn is_phase(&self, row: u32, col: u32, ele: Phase) -> Option<(u32, u32)> {
-        if !(row < self.height && col < self.width) {
-            return None; // This also works for -1 which gets converted to u32MAX
-        }
+        let (row, col) = self.resolve_position(row, col)?;
         let idx = self.get_index(row, col);
-        if self.cells[idx].phase() == ele {
+        if self.cells[idx].phase() == ele && !self.cells[idx].pinned {
             return Some((row, col));
         }
         None
     }
 
+    /// The position `down` cells along gravity and `side` cells across it from a cell.
+    /// Positions past the top or left edge come out near `u32::MAX`, like they do with
+    /// `wrapping_sub`, so the bounds helpers treat them as off the grid.
+    fn toward_gravity(&self, row: u32, col: u32, down: i64, side: i64) -> (u32, u32) {
+        let (dr, dc) = match self.gravity {
+            GravityDir::Down => (down, side),
+            GravityDir::Up => (-down, side),
+            GravityDir::Left => (side, -down),
+            GravityDir::Right => (side, down),
+        };
+        ((row as i64 + dr) as u32, (col as i64 + dc) as u32)
+    }
+
+    /// Whether no unpinned liquid cell has anywhere to flow to, see `is_stable`
+    fn liquids_stable(&self) -> bool {
+        self.gravity_order().all(|(row, col)| {
+            let cell = self.cells[self.get_index(row, col)];
+            cell.phase() != Phase::Liquid || cell.pinned || self.is_stable(row, col)
+        })
+    }
+
+    /// The position right below a cell in the gravity direction, wrapped in wrap mode, or
+    /// None if that is off the simulated grid
+    fn below(&self, row: u32, col: u32) -> Option<(u32, u32)> {
+        let (below_row, below_col) = self.toward_gravity(row, col, 1, 0);
+        self.resolve_position(below_row, below_col)
+    }
+
+    /// Every position of the grid, line by line across gravity starting from the side it
+    /// pulls towards, for passes that don't need the shuffling and skipping of `step`
+    fn gravity_order(&self) -> impl Iterator<Item = (u32, u32)> {
+        let across_rows = matches!(self.gravity, GravityDir::Down | GravityDir::Up);
+        let (lines, line_length) = if across_rows { (self.height, self.width) } else { (self.width, self.height) };
+        let gravity = self.gravity;
+        (0..lines).flat_map(move |i| {
+            let line = match gravity {
+                GravityDir::Down | GravityDir::Right => lines - 1 - i,
+                GravityDir::Up | GravityDir::Left => i,
+            };
+            (0..line_length).rev().map(move |position| if across_rows { (line, position) } else { (position, line) })
+        })
+    }
+
+    /// Up, down, left and right neighbors that are inside the grid, across the edges in
+    /// wrap mode. A cell is never its own neighbor, even on a grid one cell wide.
+    fn orthogonal_neighbors(&self, row: u32, col: u32) -> impl Iterator<Item = (u32, u32)> {
+        let (width, height) = (self.width, self.height);
+        IntoIterator::into_iter([
+            self.wrap_position(row.wrapping_sub(1), col),
+            self.wrap_position(row + 1, col),
+            self.wrap_position(row, col.wrapping_sub(1)),
+            self.wrap_position(row, col + 1),
+        ])
+        .filter(move |&(r, c)| r < height && c < width && (r, c) != (row, col))
+    }
+
+    fn count_neighbors(&self, row: u32, col: u32, ct: CellType) -> usize {
+        self.orthogonal_neighbors(row, col)
+            .filter(|&(r, c)| self.cells[self.get_index(r, c)].id == ct)
+            .count()
+    }
+
     /// Get the dead and Sand values of the entire universe.
     pub fn get_cells(&self) -> &[Cell] {
         &self.cells
     }
 
+    /// The type of every cell row by row, without collecting them into a new buffer
+    pub fn cell_ids(&self) -> impl Iterator<Item = CellType> + '_ {
+        self.cells.iter().map(|c| c.id)
+    }
+
+    /// Every non-empty cell with its index, skipping the empty ones so exporters and counts
+    /// don't have to handle them
+    pub fn occupied_cells(&self) -> impl Iterator<Item = (u32, &Cell)> + '_ {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.id != CellType::Dead)
+            .map(|(idx, cell)| (idx as u32, cell))
+    }
+
+    /// Average (row, column) of every cell of the given type, None if there are none
+    pub fn center_of_mass(&self, ct: CellType) -> Option<(f32, f32)> {
+        let (mut count, mut row_sum, mut col_sum) = (0u64, 0u64, 0u64);
+        for (idx, cell) in self.cells.iter().enumerate() {
+            if cell.id == ct {
+                count += 1;
+                row_sum += idx as u64 / self.width as u64;
+                col_sum += idx as u64 % self.width as u64;
+            }
+        }
+        if count == 0 {
+            return None;
+        }
+        Some((row_sum as f32 / count as f32, col_sum as f32 / count as f32))
+    }
+
+    /// Smallest box holding every non-empty cell as (min row, min column, max row, max
+    /// column), all inclusive. None if the grid is empty.
+    pub fn content_bounds(&self) -> Option<(u32, u32, u32, u32)> {
+        let mut bounds: Option<(u32, u32, u32, u32)> = None;
+        for (idx, _) in self.occupied_cells() {
+            let (row, col) = (idx / self.width, idx % self.width);
+            bounds = Some(match bounds {
+                Some((r0, c0, r1, c1)) => (r0.min(row), c0.min(col), r1.max(row), c1.max(col)),
+                None => (row, col, row, col),
+            });
+        }
+        bounds
+    }
+
     fn find_valid_positions(&self, positions: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
         positions
             .iter()
@@ -113,12 +905,26 @@ impl Universe {
             .collect::<Vec<_>>()
     }
 
-    fn find_valid_positions_for_solid(&self, positions: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+    /// Whether the cell at a position is a liquid or gas lighter than `density` that a
+    /// heavier cell can sink through by swapping places with it
+    fn is_lighter_fluid(&self, row: u32, col: u32, density: u32) -> Option<(u32, u32)> {
+        let (row, col) = self.resolve_position(row, col)?;
+        let cell = self.cells[self.get_index(row, col)];
+        let is_fluid = matches!(cell.phase(), Phase::Liquid | Phase::Gas);
+        if is_fluid && !cell.pinned && cell.density() < density {
+            return Some((row, col));
+        }
+        None
+    }
+
+    /// Like `find_valid_positions`, but a cell of this density can also sink through
+    /// lighter liquids and gases
+    fn find_valid_positions_sinking(&self, positions: Vec<(u32, u32)>, density: u32) -> Vec<(u32, u32)> {
         positions
             .iter()
             .map(|x| {
                 self.is_empty_and_inbound(x.0, x.1)
-                    .or(self.is_phase(x.0, x.1, Phase::Liquid))
+                    .or(self.is_lighter_fluid(x.0, x.1, density))
             })
             .take_while(|x| x.is_some())
             .flatten()
@@ -126,174 +932,2812 @@ impl Universe {
     }
 
 
-    fn switch_cells(&mut self, old_idx: usize, new_idx: usize) {
-        self.cells.swap(old_idx, new_idx)
+    /// A random number in [0, 1) from the universe's own generator
+    fn random(&self) -> f64 {
+        self.rng.next_f64()
     }
 
+    /// Decides which side a cell tries first, the side bias is the chance of picking right
+    fn prefers_right(&self) -> bool {
+        self.random() < self.side_bias
+    }
 
-    fn update_sand(&mut self, row: u32, col: u32) {
-        let idx = self.get_index(row, col);
-        self.cells[idx].has_been_updated = true;
-        let cell_energy = self.cells[idx].energy / 4;
-
-        let downwards_positions: Vec<_> = (1..=cell_energy + 1).map(|i| (row + i, col)).collect();
-        let left_positions = vec![(row + 1, col - 1)];
-        let right_positions = vec![(row + 1, col + 1)];
-        let side_positions = if random() > 0.5f64 {
-            // cant use system dependant rand in wasm
-            vec![left_positions, right_positions].concat()
+    /// Valid positions along the preferred side, or along the other side when the
+    /// preferred one is blocked. With a `sinking_density` the cell can also move into
+    /// lighter fluids.
+    fn find_valid_side_positions(
+        &self,
+        left_positions: Vec<(u32, u32)>,
+        right_positions: Vec<(u32, u32)>,
+        sinking_density: Option<u32>,
+    ) -> Vec<(u32, u32)> {
+        let (first, second) = if self.prefers_right() {
+            (right_positions, left_positions)
         } else {
-            vec![right_positions, left_positions].concat()
+            (left_positions, right_positions)
         };
-
-        let empty_downwards_positions = self.find_valid_positions_for_solid(downwards_positions);
-        let empty_side_positions = self.find_valid_positions_for_solid(side_positions);
-
-        if let Some(down_pos) = empty_downwards_positions.last() {
-            self.cells[idx].energy += 1; // When objects are falling they gain energy
-            let new_idx = self.get_index(down_pos.0, down_pos.1);
-            self.switch_cells(idx, new_idx);
-        } else if let Some(side_pos) = empty_side_positions.last() {
-            let new_idx = self.get_index(side_pos.0, side_pos.1);
-            self.switch_cells(idx, new_idx);
+        let find_valid = |positions| match sinking_density {
+            Some(density) => self.find_valid_positions_sinking(positions, density),
+            None => self.find_valid_positions(positions),
+        };
+        let positions = find_valid(first);
+        if positions.is_empty() {
+            find_valid(second)
         } else {
-            self.cells[idx].energy = 0;
+            positions
         }
     }
 
-    fn update_water(&mut self, row: u32, col: u32) {
-        let idx = self.get_index(row, col);
-        self.cells[idx].has_been_updated = true;
-        let cell_energy = self.cells[idx].energy;
-
-        let downwards_positions: Vec<_> = (1..=cell_energy + 1).map(|i| (row + i, col)).collect();
-        let left_down_positions = vec![(row + 1, col - 1)];
-        let right_down_positions = vec![(row + 1, col + 1)];
-        let left_positions: Vec<_> = (1..=SPREAD_FACTOR).map(|i| (row, col - i)).collect();
-        let right_positions: Vec<_> = (1..=SPREAD_FACTOR).map(|i| (row, col + i)).collect();
-
-        let side_down_positions = if random() > 0.5f64 {
-            vec![left_down_positions, right_down_positions].concat()
-        } else {
-            vec![right_down_positions, left_down_positions].concat()
-        };
-        let side_positions = if random() > 0.5f64 {
-            vec![left_positions, right_positions].concat()
-        } else {
-            vec![right_positions, left_positions].concat()
-        };
-
-        let empty_downwards_positions = self.find_valid_positions(downwards_positions);
-        let empty_side_positions = self.find_valid_positions(side_positions);
-        let empty_side_down_positions = self.find_valid_positions(side_down_positions);
+    /// Total amount of every element, water is measured by fill and the rest by cell count
+    #[cfg(feature = "conservation-check")]
+    fn material_amounts(&self) -> Vec<i64> {
+        let mut amounts = vec![0; all_elements().len()];
+        for (_, cell) in self.occupied_cells() {
+            amounts[cell.id as usize] += Universe::amount(cell);
+        }
+        amounts
+    }
 
-        if let Some(down_pos) = empty_downwards_positions.last() {
-            self.cells[idx].energy += 1; // When objects are falling they gain energy
-            let new_idx = self.get_index(down_pos.0, down_pos.1);
-            self.switch_cells(idx, new_idx);
-        } else if let Some(side_down_pos) = empty_side_down_positions.last() {
-            let new_idx = self.get_index(side_down_pos.0, side_down_pos.1);
-            self.switch_cells(idx, new_idx);
-        } else if let Some(side_pos) = empty_side_positions.last() {
-            let new_idx = self.get_index(side_pos.0, side_pos.1);
-            self.cells[idx].energy = 0;
-            self.switch_cells(idx, new_idx);
-        } else {
-            self.cells[idx].energy = 0;
+    #[cfg(feature = "conservation-check")]
+    fn amount(cell: &Cell) -> i64 {
+        match cell.id {
+            CellType::Dead => 0,
+            CellType::Water => cell.fill as i64,
+            _ => 1,
         }
     }
 
-    fn update_rock(&mut self, row: u32, col: u32) {
-        let idx = self.get_index(row, col);
-        self.cells[idx].has_been_updated = true
+    /// Records a change in the amount of an element made by a reaction rather than by
+    /// movement. Compiles to nothing without the `conservation-check` feature.
+    #[allow(unused_variables)]
+    fn sanction(&mut self, cell: Cell, sign: i64) {
+        #[cfg(feature = "conservation-check")]
+        if let Some(sanctioned) = self.conservation.sanctioned.get_mut(cell.id as usize) {
+            *sanctioned += sign * Universe::amount(&cell);
+        }
     }
-}
 
+    /// Drops `rain_rate` cells on random columns of the top row, the fractional part of
+    /// the rate is the chance of one extra cell. Columns that are already taken are skipped.
+    fn rain(&mut self) {
+        let extra = if self.random() < self.rain_rate.fract() { 1 } else { 0 };
+        let drops = self.rain_rate as u32 + extra;
+        for _ in 0..drops {
+            let col = (self.random() * self.width as f64) as u32;
+            if self.is_empty_and_inbound(0, col).is_some() {
+                let idx = self.get_index(0, col);
+                self.cells[idx] = Cell::new(self.rain_type);
+                self.sanction(self.cells[idx], 1);
+            }
+        }
+    }
 
-/// Public methods, exported to JavaScript.
-#[wasm_bindgen]
-impl Universe {
-    pub fn tick(&mut self) {
-        for row in (0..self.height).rev() {
-            for col in (0..self.width).rev() {
-                let idx = self.get_index(row, col);
-                let cell = self.cells[idx];
-                if cell.has_been_updated {
-                    continue;
+    /// Keeps every regulated column range filled up to its target row. Cells of the
+    /// regulated type above the target row are removed from the top down, then the
+    /// columns whose target row is still empty get a new cell there that settles onto the
+    /// pool. Each regulator adds or removes at most its flow rate of cells per tick.
+    fn regulate_levels(&mut self) {
+        for regulator in self.level_regulators.clone() {
+            if regulator.col_start >= self.width || regulator.target_row >= self.height {
+                continue;
+            }
+            let columns = regulator.col_start..=regulator.col_end.min(self.width - 1);
+            let mut budget = regulator.flow_rate;
+            for row in 0..regulator.target_row {
+                for col in columns.clone() {
+                    let idx = self.get_index(row, col);
+                    let cell = self.cells[idx];
+                    let overflowing = cell.id == regulator.cell_type && !cell.pinned && self.is_simulated(row, col);
+                    if budget > 0 && overflowing {
+                        self.transmute(idx, CellType::Dead);
+                        budget -= 1;
+                    }
                 }
-                match cell.id {
-                    CellType::Dead => (),
-                    CellType::Sand => self.update_sand(row, col),
-                    CellType::Water => self.update_water(row, col),
-                    CellType::Rock => self.update_rock(row, col),
+            }
+            for col in columns {
+                if budget > 0 && self.is_empty_and_inbound(regulator.target_row, col).is_some() {
+                    let idx = self.get_index(regulator.target_row, col);
+                    self.transmute(idx, regulator.cell_type);
+                    budget -= 1;
                 }
             }
         }
+    }
 
+    /// Applies the reactions added from javascript to touching pairs of cells. Every cell
+    /// takes part in at most one reaction per tick. All the pairs that would react are
+    /// collected first and then resolved in a random order, so a cell touching several
+    /// partners doesn't always react with the one the loop reaches first.
+    fn react(&mut self) {
+        if self.reactions.is_empty() {
+            return;
+        }
+        let mut candidates = Vec::new();
         for row in 0..self.height {
             for col in 0..self.width {
                 let idx = self.get_index(row, col);
-                self.cells[idx].has_been_updated = false;
+                // Only looking right and down visits every touching pair once
+                for &(r, c) in &[self.wrap_position(row, col + 1), self.wrap_position(row + 1, col)] {
+                    if !self.is_simulated(row, col) || !self.is_simulated(r, c) || (r, c) == (row, col) {
+                        continue;
+                    }
+                    let other_idx = self.get_index(r, c);
+                    let (cell, other) = (self.cells[idx], self.cells[other_idx]);
+                    if cell.pinned || other.pinned {
+                        continue;
+                    }
+                    let outputs = self.reactions.iter().find_map(|rule| {
+                        if (rule.a, rule.b) == (cell.id, other.id) {
+                            Some((rule.out_a, rule.out_b, rule.probability))
+                        } else if (rule.b, rule.a) == (cell.id, other.id) {
+                            Some((rule.out_b, rule.out_a, rule.probability))
+                        } else {
+                            None
+                        }
+                    });
+                    match outputs {
+                        Some((out, other_out, probability)) if self.random() < probability => {
+                            candidates.push((self.random(), idx, other_idx, out, other_out));
+                        }
+                        _ => (),
+                    }
+                }
             }
         }
-    }
-
-    pub fn new() -> Universe {
-        utils::set_panic_hook(); // If our code panics, we want informative error messages to appear in the developer console
-
-        let width = WIDTH;
-        let height = HEIGHT;
 
-        let cells = (0..width * height)
-            .map(|_i| Cell::new(CellType::Dead))
-            .collect();
-
-        Universe {
-            width,
-            height,
-            cells,
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut reacted = vec![false; self.cells.len()];
+        for (_, idx, other_idx, out, other_out) in candidates {
+            if reacted[idx] || reacted[other_idx] {
+                continue;
+            }
+            self.transmute(idx, out);
+            self.transmute(other_idx, other_out);
+            reacted[idx] = true;
+            reacted[other_idx] = true;
         }
     }
 
-    pub fn render_to_console(&self) -> String {
-        self.to_string()
-    }
-
-    pub fn width(&self) -> u32 {
-        self.width
+    /// Water soaks the solids next to it, which pass some of it on to their own
+    /// neighbours, and everything slowly dries out
+    fn dampen(&mut self) {
+        let humidity: Vec<u8> = self.cells.iter().map(|c| c.humidity).collect();
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let idx = self.get_index(row, col);
+                let cell = self.cells[idx];
+                self.cells[idx].humidity = if cell.id == CellType::Water {
+                    MAX_HUMIDITY
+                } else if cell.phase() == Phase::Solid {
+                    let wettest = self
+                        .orthogonal_neighbors(row, col)
+                        .map(|(r, c)| humidity[self.get_index(r, c)])
+                        .max()
+                        .unwrap_or(0);
+                    cell.humidity
+                        .saturating_sub(HUMIDITY_DECAY)
+                        .max(wettest.saturating_sub(HUMIDITY_SPREAD_LOSS))
+                } else {
+                    0
+                };
+            }
+        }
     }
 
-    pub fn height(&self) -> u32 {
+    /// Evens out the temperature of touching cells by how well both of them conduct, lets
+    /// every cell lose a little heat to its surroundings, keeps fire burning hot and caps
+    /// water at the boiling point. A cell trades at most `HEAT_DIFFUSION_RATE` of each
+    /// difference with each of its four neighbours, so its new temperature is a weighted
+    /// average of the old ones and heat can't overshoot and oscillate.
+    fn diffuse_heat(&mut self) {
+        let before = self.temperature.clone();
+        for row in 0..self.height {
+            for col in 0..self.width {
+                if !self.is_simulated(row, col) {
+                    continue;
+                }
+                let idx = self.get_index(row, col);
+                let cell = self.cells[idx];
+                let flow: f32 = self
+                    .orthogonal_neighbors(row, col)
+                    .filter(|&(r, c)| self.is_simulated(r, c))
+                    .map(|(r, c)| {
+                        let neighbor_idx = self.get_index(r, c);
+                        let conductivity = cell.conductivity().min(self.cells[neighbor_idx].conductivity());
+                        conductivity * HEAT_DIFFUSION_RATE * (before[neighbor_idx] - before[idx])
+                    })
+                    .sum();
+                let temperature = before[idx] + flow;
+                let temperature = temperature + (AMBIENT_TEMPERATURE - temperature) * HEAT_LOSS_RATE;
+                self.temperature[idx] = match cell.id {
+                    CellType::Fire => FIRE_TEMPERATURE,
+                    CellType::Water => temperature.min(BOILING_POINT), // The rest goes into boiling
+                    _ => temperature,
+                };
+            }
+        }
+    }
+
+    fn transmute(&mut self, idx: usize, ct: CellType) {
+        if self.cells[idx].id == ct {
+            return;
+        }
+        self.sanction(self.cells[idx], -1);
+        self.cells[idx] = Cell::new(ct);
+        self.sanction(self.cells[idx], 1);
+    }
+
+    /// Fisher-Yates shuffle of the column order
+    fn shuffle_columns(&self, columns: &mut [u32]) {
+        for i in (1..columns.len()).rev() {
+            let j = (self.random() * (i + 1) as f64) as usize;
+            columns.swap(i, j);
+        }
+    }
+
+    /// One pass over the grid in `self.cells`, `tick()` runs it once for every layer
+    fn step(&mut self) {
+        #[cfg(feature = "particle-ids")]
+        self.label_particles();
+        #[cfg(feature = "conservation-check")]
+        {
+            self.conservation.amounts = self.material_amounts();
+            self.conservation.sanctioned = vec![0; all_elements().len()];
+        }
+
+        self.rain();
+        self.regulate_levels();
+        self.react();
+        self.dampen();
+        let activity_decay = self.activity_decay;
+        let chunks_wide = self.width.div_ceil(CHUNK_SIZE) as usize;
+        let chunks_high = self.height.div_ceil(CHUNK_SIZE) as usize;
+        let mut lively = vec![false; chunks_wide * chunks_high];
+        for (idx, cell) in self.cells.iter_mut().enumerate() {
+            cell.activity = cell.activity.saturating_sub(activity_decay);
+            cell.motion = (0, 0);
+            if !cell.is_inert() {
+                let (row, col) = (idx / self.width as usize, idx % self.width as usize);
+                lively[row / CHUNK_SIZE as usize * chunks_wide + col / CHUNK_SIZE as usize] = true;
+            }
+        }
+        let active_chunks = if self.chunked {
+            Some(Universe::spread_chunks(&lively, chunks_wide, chunks_high))
+        } else {
+            None
+        };
+
+        // Lines across gravity are visited starting from the side gravity pulls towards,
+        // so a cell has already moved out of the way when the one behind it falls
+        let across_rows = matches!(self.gravity, GravityDir::Down | GravityDir::Up);
+        let (lines, line_length) = if across_rows { (self.height, self.width) } else { (self.width, self.height) };
+        // Lines that don't cross an active chunk are skipped whole, only their shuffle is
+        // kept so the random numbers drawn match those of a full scan
+        let active_lines = active_chunks.as_ref().map(|active_chunks| {
+            let chunk_lines = (0..active_chunks.len()).filter(|&chunk| active_chunks[chunk]).map(|chunk| {
+                let chunk_line = if across_rows { chunk / chunks_wide } else { chunk % chunks_wide } as u32;
+                (chunk_line * CHUNK_SIZE, ((chunk_line + 1) * CHUNK_SIZE).min(lines) - 1)
+            });
+            chunk_lines.fold(None, |range: Option<(u32, u32)>, (first, last)| match range {
+                Some((lo, hi)) => Some((lo.min(first), hi.max(last))),
+                None => Some((first, last)),
+            })
+        });
+        // Alternating the direction keeps piles from leaning towards the side scanned first
+        let mut columns: Vec<u32> = if self.tick_parity {
+            (0..line_length).rev().collect()
+        } else {
+            (0..line_length).collect()
+        };
+        for i in 0..lines {
+            let line = match self.gravity {
+                GravityDir::Down | GravityDir::Right => lines - 1 - i,
+                GravityDir::Up | GravityDir::Left => i,
+            };
+            if self.scan_mode == ScanMode::Shuffled {
+                self.shuffle_columns(&mut columns);
+            }
+            if let Some(active_lines) = active_lines {
+                if !active_lines.is_some_and(|(first, last)| (first..=last).contains(&line)) {
+                    continue;
+                }
+            }
+            for &position in &columns {
+                let (row, col) = if across_rows { (line, position) } else { (position, line) };
+                if self.active_bounds.is_some() && !self.is_simulated(row, col) {
+                    continue;
+                }
+                if let Some(active_chunks) = &active_chunks {
+                    let chunk = (row / CHUNK_SIZE) as usize * chunks_wide + (col / CHUNK_SIZE) as usize;
+                    if !active_chunks[chunk] {
+                        continue;
+                    }
+                }
+                let idx = self.get_index(row, col);
+                let cell = self.cells[idx];
+                if self.is_updated(&cell) || cell.pinned {
+                    continue;
+                }
+                let interval = self.update_intervals[cell.id as usize] as u64;
+                if !self.generation.is_multiple_of(interval) {
+                    continue;
+                }
+                let movable = matches!(cell.phase(), Phase::Solid | Phase::Liquid | Phase::Gas);
+                if movable && !self.gravity_wells.is_empty() && self.fall_into_well(row, col) {
+                    continue;
+                }
+                match cell.id {
+                    CellType::Dead => (),
+                    CellType::Sand => self.update_sand(row, col),
+                    CellType::Water => self.update_water(row, col),
+                    CellType::Rock => self.update_rock(row, col),
+                    CellType::Quicksand => self.update_quicksand(row, col),
+                    CellType::Vine => self.update_vine(row, col),
+                    CellType::Geyser => self.update_geyser(row, col),
+                    CellType::Tar => self.update_tar(row, col),
+                    CellType::Slime => self.update_slime(row, col),
+                    CellType::Nitro => self.update_nitro(row, col),
+                    CellType::Dust => self.update_dust(row, col),
+                    CellType::Magnet => self.update_rock(row, col),
+                    CellType::IronFilings => self.update_iron_filings(row, col),
+                    CellType::Cloud => self.update_cloud(row, col),
+                    CellType::Frost => self.update_frost(row, col),
+                    CellType::Ice => self.update_rock(row, col),
+                    CellType::Methane => self.update_methane(row, col),
+                    CellType::Rubber => self.update_rock(row, col),
+                    CellType::PackedSnow => self.update_rock(row, col),
+                    CellType::Snow => self.update_sand(row, col),
+                    CellType::WetConcrete => self.update_wet_concrete(row, col),
+                    CellType::Oil => self.update_liquid(row, col, SPREAD_FACTOR),
+                    CellType::Fire => self.update_fire(row, col),
+                    CellType::Steam => self.update_steam(row, col),
+                    CellType::Acid => self.update_acid(row, col),
+                }
+            }
+        }
+
+        self.diffuse_heat();
+        self.reset_updated();
+        #[cfg(feature = "particle-ids")]
+        self.label_particles();
+
+        #[cfg(feature = "conservation-check")]
+        {
+            let amounts = self.material_amounts();
+            let conservation = &mut self.conservation;
+            conservation.conserved &= amounts
+                .iter()
+                .zip(&conservation.amounts)
+                .zip(&conservation.sanctioned)
+                .all(|((&now, &before), &sanctioned)| now == before + sanctioned);
+            conservation.amounts = amounts;
+        }
+    }
+
+    /// Swaps the cells of a layer in or out of `self.cells`, doing it twice undoes it
+    fn swap_layer(&mut self, layer: usize) {
+        std::mem::swap(&mut self.cells, &mut self.layers[layer].cells);
+        std::mem::swap(&mut self.temperature, &mut self.layers[layer].temperature);
+        std::mem::swap(&mut self.swap_streaks, &mut self.layers[layer].swap_streaks);
+        std::mem::swap(&mut self.update_stamp, &mut self.layers[layer].update_stamp);
+    }
+
+    /// A row by row buffer for a grid of `new_size` (width, height) holding the values of
+    /// `old` where the positions overlap, and `empty` everywhere else
+    fn copy_overlap<T: Copy>(old: &[T], empty: T, old_size: (u32, u32), new_size: (u32, u32)) -> Vec<T> {
+        let ((old_width, old_height), (new_width, new_height)) = (old_size, new_size);
+        let mut values = vec![empty; (new_width * new_height) as usize];
+        for row in 0..old_height.min(new_height) {
+            for col in 0..old_width.min(new_width) {
+                values[(row * new_width + col) as usize] = old[(row * old_width + col) as usize];
+            }
+        }
+        values
+    }
+
+    /// Changes the grid size, keeping every cell whose position is inside both the old and
+    /// the new grid. New areas are empty and cells outside the new grid are dropped. Every
+    /// layer is resized the same way. Swap streaks are keyed by index, so they start over.
+    fn resize(&mut self, new_width: u32, new_height: u32) {
+        let (old_size, new_size) = ((self.width, self.height), (new_width, new_height));
+        let empty = Cell::new(CellType::Dead);
+        self.cells = Universe::copy_overlap(&self.cells, empty, old_size, new_size);
+        self.temperature = Universe::copy_overlap(&self.temperature, AMBIENT_TEMPERATURE, old_size, new_size);
+        for (index, layer) in self.layers.iter_mut().enumerate() {
+            if index != self.active_layer {
+                layer.cells = Universe::copy_overlap(&layer.cells, empty, old_size, new_size);
+                layer.temperature =
+                    Universe::copy_overlap(&layer.temperature, AMBIENT_TEMPERATURE, old_size, new_size);
+            }
+            layer.swap_streaks.clear();
+        }
+        self.width = new_width;
+        self.height = new_height;
+        self.swap_streaks.clear();
+        self.held.clear();
+    }
+
+    /// Paints one cell for `set_cell`, `stamp` and the other painting helpers, sinking it
+    /// through lighter fluids below when density aware painting is on
+    fn paint(&mut self, row: u32, col: u32, ct: CellType) {
+        let mut idx = self.get_index(row, col);
+        self.cells[idx].set_cell(ct);
+        let cell = self.cells[idx];
+        let sinks = matches!(cell.phase(), Phase::Solid | Phase::Liquid);
+        if !self.density_aware_paint || !sinks || cell.pinned {
+            return;
+        }
+        for distance in 1..=self.width.max(self.height) as i64 {
+            let (below_row, below_col) = self.toward_gravity(row, col, distance, 0);
+            if !(below_row < self.height && below_col < self.width) {
+                break;
+            }
+            let below_idx = self.get_index(below_row, below_col);
+            let below = self.cells[below_idx];
+            let is_fluid = matches!(below.phase(), Phase::Liquid | Phase::Gas);
+            if !is_fluid || below.pinned || below.density() >= cell.density() {
+                break;
+            }
+            self.cells.swap(idx, below_idx);
+            idx = below_idx;
+        }
+    }
+
+    /// Gives every unlabelled cell a fresh particle id and takes the id away from empty
+    /// cells. Runs before and after each tick, so cells painted in between are labelled
+    /// before they move and cells spawned during the tick right after it.
+    #[cfg(feature = "particle-ids")]
+    fn label_particles(&mut self) {
+        for cell in self.cells.iter_mut() {
+            if cell.id == CellType::Dead {
+                cell.particle = 0;
+            } else if cell.particle == 0 {
+                cell.particle = self.next_particle;
+                self.next_particle += 1;
+            }
+        }
+    }
+
+    fn mark_updated(&mut self, idx: usize) {
+        self.cells[idx].update_stamp = self.update_stamp;
+    }
+
+    fn is_updated(&self, cell: &Cell) -> bool {
+        cell.update_stamp == self.update_stamp
+    }
+
+    /// Ends a pass by moving on to the next update stamp, which un-marks every cell without
+    /// visiting them. Cells held still by `track_swap` start the next pass already updated.
+    fn reset_updated(&mut self) {
+        self.update_stamp = self.update_stamp.checked_add(1).unwrap_or(1);
+        if self.update_stamp == 1 {
+            // The stamps wrapped around, so one left from long ago could pass for a new one.
+            // Clearing them once every 255 passes is far cheaper than after every pass.
+            for cell in &mut self.cells {
+                cell.update_stamp = 0;
+            }
+        }
+        for &idx in &self.held {
+            self.cells[idx].update_stamp = self.update_stamp;
+        }
+        self.held.clear();
+        let pass = self.pass;
+        self.swap_streaks.retain(|_, streak| streak.last_pass == pass);
+        self.pass += 1;
+    }
+
+    /// Counts how many passes in a row two positions swapped the same two cells back and
+    /// forth. Once that goes past the oscillation limit the cell that just moved is held
+    /// still for a pass, which breaks the cycle. A steady stream through the pair brings
+    /// new cells every pass, so it never counts as going back and forth.
+    fn track_swap(&mut self, old_idx: usize, new_idx: usize) {
+        if self.oscillation_limit == 0 {
+            return;
+        }
+        let key = (old_idx.min(new_idx), old_idx.max(new_idx));
+        if !self.swap_streaks.contains_key(&key) && self.swap_streaks.len() >= MAX_TRACKED_SWAPS {
+            return;
+        }
+        let pass = self.pass;
+        let after = (self.cells[key.0].id, self.cells[key.1].id);
+        let streak = self.swap_streaks.entry(key).or_insert(SwapStreak { last_pass: pass, passes: 0, after });
+        if streak.passes > 0 && streak.last_pass == pass {
+            streak.after = after;
+            return; // Swapped twice in one pass, still one pass of the streak
+        }
+        // The cells went back to where they were before the last swap only if what the
+        // pair held then is what it held right before this swap
+        let reversed = streak.after == (after.1, after.0);
+        streak.passes = if reversed { streak.passes + 1 } else { 1 };
+        streak.last_pass = pass;
+        streak.after = after;
+        if streak.passes > self.oscillation_limit {
+            self.swap_streaks.remove(&key);
+            self.cells[new_idx].energy = 0;
+            self.held.push(new_idx);
+        }
+    }
+
+    /// Moves the cell at `old_idx` to `new_idx`. Whole cells are swapped, so the displaced
+    /// cell keeps its own update stamp: it is skipped for the rest of the tick
+    /// only if it has already been updated, and the mover is always marked as updated.
+    fn switch_cells(&mut self, old_idx: usize, new_idx: usize) {
+        self.cells.swap(old_idx, new_idx);
+        self.mark_updated(new_idx);
+        self.cells[old_idx].activity = MAX_ACTIVITY;
+        self.cells[new_idx].activity = MAX_ACTIVITY;
+        self.track_swap(old_idx, new_idx);
+        let width = self.width as i64;
+        let (old, new) = (old_idx as i64, new_idx as i64);
+        let (dx, dy) = (new % width - old % width, new / width - old / width);
+        for (idx, sign) in [(new_idx, 1), (old_idx, -1)] {
+            let motion = &mut self.cells[idx].motion;
+            motion.0 = (motion.0 as i64 + sign * dx).clamp(i8::MIN as i64, i8::MAX as i64) as i8;
+            motion.1 = (motion.1 as i64 + sign * dy).clamp(i8::MIN as i64, i8::MAX as i64) as i8;
+        }
+        if self.cells[new_idx].id == CellType::Water {
+            self.cells[new_idx].flow = self.flow_direction(old_idx, new_idx);
+        }
+        if self.probe.is_some() {
+            self.record_move(old_idx, new_idx);
+        }
+    }
+
+    fn flow_direction(&self, old_idx: usize, new_idx: usize) -> FlowDirection {
+        let width = self.width as usize;
+        let (from_row, from_col) = (old_idx / width, old_idx % width);
+        let (to_row, to_col) = (new_idx / width, new_idx % width);
+        match (to_row.cmp(&from_row), to_col.cmp(&from_col)) {
+            (Ordering::Less, _) => FlowDirection::Up,
+            (Ordering::Equal, Ordering::Less) => FlowDirection::Left,
+            (Ordering::Equal, Ordering::Greater) => FlowDirection::Right,
+            (Ordering::Equal, Ordering::Equal) => FlowDirection::Still,
+            (Ordering::Greater, Ordering::Less) => FlowDirection::DownLeft,
+            (Ordering::Greater, Ordering::Equal) => FlowDirection::Down,
+            (Ordering::Greater, Ordering::Greater) => FlowDirection::DownRight,
+        }
+    }
+
+    fn record_move(&mut self, old_idx: usize, new_idx: usize) {
+        let width = self.width as usize;
+        let (from_row, from_col) = ((old_idx / width) as u32, (old_idx % width) as u32);
+        let (to_row, to_col) = ((new_idx / width) as u32, (new_idx % width) as u32);
+        let kind = if to_row < from_row {
+            MoveKind::Up
+        } else if to_row == from_row {
+            MoveKind::Side
+        } else if to_col == from_col {
+            MoveKind::Down
+        } else {
+            MoveKind::SideDown
+        };
+        if let Some(probe) = self.probe.as_mut() {
+            probe.push(ProbeRecord { from_row, from_col, to_row, to_col, kind });
+        }
+    }
+
+    /// Energy is momentum from falling: a straight drop adds to it, a diagonal slide is
+    /// still a fall and keeps it, and moving only sideways or coming to rest clears it.
+    /// `update_liquid` follows the same rules.
+    fn update_sand(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        let cell_energy = self.cells[idx].energy / 4;
+        let fall_distance = (cell_energy + 1).min(self.max_fall_distance);
+
+        let downwards_positions: Vec<_> =
+            (1..=fall_distance as i64).map(|i| self.toward_gravity(row, col, i, 0)).collect();
+        let left_positions: Vec<_> =
+            (1..=self.slide_reach as i64).map(|i| self.toward_gravity(row, col, i, -i)).collect();
+        let right_positions: Vec<_> =
+            (1..=self.slide_reach as i64).map(|i| self.toward_gravity(row, col, i, i)).collect();
+
+        let density = self.cells[idx].density();
+        let mut empty_downwards_positions = self.find_valid_positions_sinking(downwards_positions, density);
+        let empty_side_positions = self.find_valid_side_positions(left_positions, right_positions, Some(density));
+        let wet = self.cells[idx].humidity >= WET_SAND_HUMIDITY;
+        // The first tar cell on the way catches a falling cell
+        let first_tar = empty_downwards_positions
+            .iter()
+            .position(|&(r, c)| self.cells[self.get_index(r, c)].id == CellType::Tar);
+        if let Some(tar) = first_tar {
+            empty_downwards_positions.truncate(tar + 1);
+        }
+
+        if let Some(down_pos) = empty_downwards_positions.last() {
+            self.accelerate(idx);
+            let new_idx = self.get_index(down_pos.0, down_pos.1);
+            if self.cells[new_idx].id == CellType::Nitro && self.cells[idx].energy >= NITRO_IMPACT_ENERGY {
+                self.explode(down_pos.0, down_pos.1, NITRO_BLAST_RADIUS);
+                return;
+            }
+            if self.cells[new_idx].id == CellType::Tar {
+                // Tar is sticky enough to stop anything sinking through it, and only lets
+                // it crawl deeper now and then
+                self.cells[idx].energy = 0;
+                if self.random() >= TAR_SINK_CHANCE {
+                    return;
+                }
+            }
+            self.switch_cells(idx, new_idx);
+        } else if let Some((below_row, below_col)) = self.lands_on_nitro(row, col) {
+            self.explode(below_row, below_col, NITRO_BLAST_RADIUS);
+        } else if let Some(side_pos) = empty_side_positions.last().filter(|_| !wet) {
+            // Sand only slides diagonally downwards, so it keeps its momentum
+            let new_idx = self.get_index(side_pos.0, side_pos.1);
+            if self.cells[new_idx].id == CellType::Tar {
+                self.cells[idx].energy = 0;
+                if self.random() >= TAR_SINK_CHANCE {
+                    return;
+                }
+            }
+            self.switch_cells(idx, new_idx);
+        } else if !self.bounce(row, col) {
+            self.strike_below(row, col);
+            self.cells[idx].energy = 0;
+        }
+    }
+
+    /// A cell landing hard on rubber is thrown back up by a height that grows with its
+    /// energy, returns whether it bounced
+    fn bounce(&mut self, row: u32, col: u32) -> bool {
+        let idx = self.get_index(row, col);
+        let energy = self.cells[idx].energy;
+        let on_rubber = self
+            .below(row, col)
+            .is_some_and(|(below_row, below_col)| self.cells[self.get_index(below_row, below_col)].id == CellType::Rubber);
+        if !on_rubber || energy < BOUNCE_MIN_ENERGY {
+            return false;
+        }
+        self.cells[idx].energy = 0;
+        let height = (energy as f64 * self.rubber_restitution) as u32;
+        let upwards_positions: Vec<_> =
+            (1..=height as i64).map(|i| self.toward_gravity(row, col, -i, 0)).collect();
+        if let Some(up_pos) = self.find_valid_positions(upwards_positions).last() {
+            let new_idx = self.get_index(up_pos.0, up_pos.1);
+            self.switch_cells(idx, new_idx);
+        }
+        true
+    }
+
+    /// Where the nitroglycerin right below the cell is, if the cell is coming down hard
+    /// enough on it to set it off. Nitroglycerin is denser than most solids, so they land
+    /// on it instead of sinking into it.
+    fn lands_on_nitro(&self, row: u32, col: u32) -> Option<(u32, u32)> {
+        let energy = self.cells[self.get_index(row, col)].energy;
+        self.below(row, col).filter(|&(below_row, below_col)| {
+            energy >= NITRO_IMPACT_ENERGY && self.cells[self.get_index(below_row, below_col)].id == CellType::Nitro
+        })
+    }
+
+    /// A cell landing hard on rock damages it by its energy, landing on packed snow hard
+    /// enough breaks the snow up
+    fn strike_below(&mut self, row: u32, col: u32) {
+        let energy = self.cells[self.get_index(row, col)].energy;
+        let (below_row, below_col) = match self.below(row, col) {
+            Some(pos) if energy >= ROCK_IMPACT_MIN_ENERGY => pos,
+            _ => return,
+        };
+        let below_idx = self.get_index(below_row, below_col);
+        self.damage_rock(below_idx, energy);
+        if self.cells[below_idx].id == CellType::PackedSnow && energy >= self.packed_snow_strength {
+            self.crumble_snow(below_row, below_col);
+        }
+    }
+
+    /// Breaks the packed snow connected to a position, up to `SNOW_CRUMBLE_RADIUS` away
+    /// from it, into loose snow that falls
+    fn crumble_snow(&mut self, row: u32, col: u32) {
+        let mut stack = vec![(row, col)];
+        while let Some((r, c)) = stack.pop() {
+            let (dr, dc) = (r as i64 - row as i64, c as i64 - col as i64);
+            let idx = self.get_index(r, c);
+            let cell = self.cells[idx];
+            let in_reach = dr * dr + dc * dc <= SNOW_CRUMBLE_RADIUS * SNOW_CRUMBLE_RADIUS;
+            if cell.id != CellType::PackedSnow || cell.pinned || !in_reach || !self.is_simulated(r, c) {
+                continue;
+            }
+            self.transmute(idx, CellType::Snow);
+            stack.extend(self.orthogonal_neighbors(r, c));
+        }
+    }
+
+    /// Adds to the damage a rock cell has taken, held in its energy field, and crumbles
+    /// it into sand once the damage reaches the rock durability
+    fn damage_rock(&mut self, idx: usize, damage: u32) {
+        let rock = self.cells[idx];
+        if self.rock_durability == 0 || rock.id != CellType::Rock || rock.pinned {
+            return;
+        }
+        let total = rock.energy.saturating_add(damage);
+        if total < self.rock_durability {
+            self.cells[idx].energy = total;
+            return;
+        }
+        self.sanction(rock, -1);
+        self.cells[idx] = Cell {
+            update_stamp: self.update_stamp,
+            ..Cell::new(CellType::Sand)
+        };
+        self.sanction(self.cells[idx], 1);
+    }
+
+    /// Falling cells gain energy every tick until they reach terminal velocity
+    fn accelerate(&mut self, idx: usize) {
+        if self.cells[idx].energy < self.terminal_velocity {
+            self.cells[idx].energy += 1;
+        }
+    }
+
+    fn update_water(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.cells[idx].flow = FlowDirection::Still; // Set again by `switch_cells` if the water moves
+        if self.boil(row, col) || self.erode(row, col) {
+            return;
+        }
+        if self.holds_together(row, col) {
+            self.update_liquid(row, col, 0); // Falls and rolls off edges but doesn't spread out
+            return;
+        }
+        if self.level_out(row, col) {
+            return;
+        }
+        if !self.share_fill(row, col) {
+            self.update_liquid(row, col, SPREAD_FACTOR);
+        }
+    }
+
+    /// Water heated to the boiling point turns into steam now and then, returns whether
+    /// it did
+    fn boil(&mut self, row: u32, col: u32) -> bool {
+        let idx = self.get_index(row, col);
+        if self.temperature[idx] < BOILING_POINT || self.random() >= BOIL_CHANCE {
+            return false;
+        }
+        self.transmute(idx, CellType::Steam);
+        self.mark_updated(idx);
+        true
+    }
+
+    /// Whether surface tension keeps a small droplet resting on something from spreading
+    /// this tick. Water lying on other water is never held.
+    fn holds_together(&self, row: u32, col: u32) -> bool {
+        let on_water = self
+            .below(row, col)
+            .is_some_and(|(below_row, below_col)| self.cells[self.get_index(below_row, below_col)].id == CellType::Water);
+        if on_water {
+            return false;
+        }
+        self.count_neighbors(row, col, CellType::Water) <= DROPLET_MAX_NEIGHBORS
+            && self.random() < self.surface_tension
+    }
+
+    /// Moves resting surface water to the surface of the same body of water where it is at
+    /// least one cell lower, like communicating vessels, so water on both sides of a wall
+    /// it flows under evens out without having to spread there one cell at a time. The
+    /// search only goes through water below the cell and within `PRESSURE_REACH` of it,
+    /// so its cost stays bounded. Returns whether the water moved.
+    fn level_out(&mut self, row: u32, col: u32) -> bool {
+        let (above_row, above_col) = self.toward_gravity(row, col, -1, 0);
+        let is_water = |universe: &Universe, (r, c): (u32, u32)| {
+            universe.resolve_position(r, c).is_some_and(|(r, c)| {
+                let cell = universe.cells[universe.get_index(r, c)];
+                cell.id == CellType::Water && !cell.pinned
+            })
+        };
+        if is_water(self, (above_row, above_col)) {
+            return false; // Only the surface moves, the water under it follows by falling
+        }
+        let can_flow = [(1, 0), (1, -1), (1, 1)].iter().any(|&(down, side)| {
+            let (r, c) = self.toward_gravity(row, col, down, side);
+            self.is_empty_and_inbound(r, c).is_some()
+        });
+        if can_flow || self.random() >= PRESSURE_CHANCE {
+            return false;
+        }
+
+        // Breadth first through the water, counting down and sideways from the cell
+        let window_width = 2 * PRESSURE_REACH + 1;
+        let mut visited = vec![false; ((PRESSURE_DEPTH + 1) * window_width) as usize];
+        let mut queue = std::collections::VecDeque::from(vec![(0, 0)]);
+        visited[PRESSURE_REACH as usize] = true;
+        let (mut target, mut visits) = (None, 0);
+        while let Some((down, side)) = queue.pop_front() {
+            visits += 1;
+            if visits > MAX_PRESSURE_CELLS {
+                break;
+            }
+            let (r, c) = self.toward_gravity(row, col, down - 1, side);
+            if down >= 2 && target.is_none_or(|(target_down, _)| down - 1 > target_down) {
+                if let Some(pos) = self.is_empty_and_inbound(r, c) {
+                    target = Some((down - 1, pos));
+                }
+            }
+            for (next_down, next_side) in [(down + 1, side), (down - 1, side), (down, side - 1), (down, side + 1)] {
+                let in_window = (0..=PRESSURE_DEPTH).contains(&next_down) && next_side.abs() <= PRESSURE_REACH;
+                if !in_window {
+                    continue;
+                }
+                let slot = (next_down * window_width + next_side + PRESSURE_REACH) as usize;
+                if !visited[slot] && is_water(self, self.toward_gravity(row, col, next_down, next_side)) {
+                    visited[slot] = true;
+                    queue.push_back((next_down, next_side));
+                }
+            }
+        }
+
+        match target {
+            Some((_, (target_row, target_col))) => {
+                let (idx, target_idx) = (self.get_index(row, col), self.get_index(target_row, target_col));
+                self.cells[idx].energy = 0;
+                self.switch_cells(idx, target_idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fast moving water can knock a neighbouring sand grain into the flow by trading
+    /// places with it, the grain then sinks and settles further downstream
+    fn erode(&mut self, row: u32, col: u32) -> bool {
+        let idx = self.get_index(row, col);
+        if self.cells[idx].energy < EROSION_MIN_ENERGY || self.random() >= self.erosion_rate {
+            return false;
+        }
+        let grain = self.orthogonal_neighbors(row, col).find(|&(r, c)| {
+            let neighbor = self.cells[self.get_index(r, c)];
+            neighbor.id == CellType::Sand && !neighbor.pinned && self.is_simulated(r, c)
+        });
+        if let Some((r, c)) = grain {
+            let grain_idx = self.get_index(r, c);
+            self.cells[idx].energy = 0; // Carrying the grain costs the water its momentum
+            self.mark_updated(grain_idx);
+            self.switch_cells(idx, grain_idx);
+            return true;
+        }
+        false
+    }
+
+    /// Moves part of a water cell's fill into partly filled water below it, levels it out
+    /// with emptier water beside it, or splits it into an empty cell beside it.
+    /// Returns false when no fill was moved so the cell can flow as a whole instead.
+    fn share_fill(&mut self, row: u32, col: u32) -> bool {
+        let idx = self.get_index(row, col);
+        let fill = self.cells[idx].fill;
+
+        // On a grid one cell high wrapping around, the cell is below itself
+        if let Some((below_row, below_col)) = self.below(row, col).filter(|&pos| pos != (row, col)) {
+            let below_idx = self.get_index(below_row, below_col);
+            let below = self.cells[below_idx];
+            if below.id == CellType::Dead {
+                return false; // Falling is handled by the normal liquid movement
+            }
+            if below.id == CellType::Water && below.fill < MAX_FILL && !below.pinned {
+                let amount = fill.min(MAX_FILL - below.fill);
+                self.cells[below_idx].fill += amount;
+                self.remove_fill(idx, amount);
+                return true;
+            }
+        }
+
+        let can_slide = [-1, 1].iter().any(|&side| {
+            let (r, c) = self.toward_gravity(row, col, 1, side);
+            self.is_empty_and_inbound(r, c).is_some()
+        });
+        if can_slide {
+            return false; // Flowing diagonally down also moves the cell as a whole
+        }
+        let (left, right) = (self.toward_gravity(row, col, 0, -1), self.toward_gravity(row, col, 0, 1));
+        let side_positions = if self.prefers_right() { [right, left] } else { [left, right] };
+        for &(r, c) in &side_positions {
+            let (r, c) = match self.resolve_position(r, c).filter(|&pos| pos != (row, col)) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let side_idx = self.get_index(r, c);
+            let side = self.cells[side_idx];
+            if side.pinned {
+                continue;
+            }
+            if side.id == CellType::Water && side.fill < fill.saturating_sub(1) {
+                let amount = (fill - side.fill) / 2;
+                self.cells[side_idx].fill += amount;
+                self.remove_fill(idx, amount);
+                return true;
+            }
+            if side.id == CellType::Dead && fill >= 2 * MIN_SPLIT_FILL {
+                let amount = fill / 2;
+                self.cells[side_idx] = Cell {
+                    fill: amount,
+                    update_stamp: self.update_stamp,
+                    ..Cell::new(CellType::Water)
+                };
+                self.remove_fill(idx, amount);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Takes fill out of a water cell, emptying the cell once nothing is left
+    fn remove_fill(&mut self, idx: usize, amount: u8) {
+        self.mark_updated(idx);
+        self.cells[idx].fill -= amount;
+        if self.cells[idx].fill == 0 {
+            self.cells[idx] = Cell::new(CellType::Dead);
+        }
+    }
+
+    /// Slime only moves where it stays stuck to the rest of its blob. It falls a single
+    /// cell at a time, and since lines are scanned from the side gravity pulls towards a
+    /// whole blob drops together.
+    fn update_slime(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        let (below_row, below_col) = self.toward_gravity(row, col, 1, 0);
+        if let Some(down_pos) = self.is_empty_and_inbound(below_row, below_col) {
+            let new_idx = self.get_index(down_pos.0, down_pos.1);
+            self.switch_cells(idx, new_idx);
+            return;
+        }
+        if self.random() > SLIME_JIGGLE_CHANCE {
+            return;
+        }
+
+        let first_side = if self.prefers_right() { 1 } else { -1 };
+        let side_positions = [(1, first_side), (1, -first_side), (0, first_side), (0, -first_side)];
+        for &(down, side) in &side_positions {
+            let (r, c) = self.toward_gravity(row, col, down, side);
+            let (r, c) = match self.is_empty_and_inbound(r, c) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let mut slime_neighbors = self.count_neighbors(r, c, CellType::Slime);
+            if down == 0 {
+                slime_neighbors -= 1; // Do not count the cell that is moving
+            }
+            if slime_neighbors > 0 {
+                let new_idx = self.get_index(r, c);
+                self.switch_cells(idx, new_idx);
+                return;
+            }
+        }
+    }
+
+    /// Nitroglycerin flows like water but blows up when it lands hard
+    fn update_nitro(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        let density = self.cells[idx].density();
+        let below = self.toward_gravity(row, col, 1, 0);
+        let is_landing = self.find_valid_positions_sinking(vec![below], density).is_empty();
+        if is_landing && self.cells[idx].energy >= NITRO_IMPACT_ENERGY {
+            self.explode(row, col, NITRO_BLAST_RADIUS);
+            return;
+        }
+        self.update_liquid(row, col, SPREAD_FACTOR);
+    }
+
+    fn push_event(&mut self, kind: EventKind, row: u32, col: u32) {
+        if self.events.len() < MAX_QUEUED_EVENTS {
+            self.events.push(Event { kind, row, col });
+        }
+    }
+
+    /// Adds to a sound meter, remembering where so the event can be placed there
+    fn make_sound(&mut self, kind: SoundKind, amount: u32, row: u32, col: u32) {
+        let meter = &mut self.sound_meters[kind as usize];
+        meter.level = meter.level.saturating_add(amount);
+        meter.row = row;
+        meter.col = col;
+    }
+
+    /// Queues an event for every sound meter that crossed its threshold and empties them
+    fn sound_off(&mut self) {
+        for kind in [SoundKind::Splash, SoundKind::Rumble] {
+            let meter = self.sound_meters[kind as usize];
+            if meter.threshold > 0 && meter.level >= meter.threshold {
+                let event = match kind {
+                    SoundKind::Splash => EventKind::Splash,
+                    SoundKind::Rumble => EventKind::Rumble,
+                };
+                self.push_event(event, meter.row, meter.col);
+            }
+            self.sound_meters[kind as usize].level = 0;
+        }
+    }
+
+    /// Clears every movable cell within `radius`, setting off any nitroglycerin caught in
+    /// the blast. Immovable and pinned cells survive.
+    fn explode(&mut self, row: u32, col: u32, radius: u32) {
+        let mut blasts = vec![(row, col)];
+        while let Some((blast_row, blast_col)) = blasts.pop() {
+            self.push_event(EventKind::Explosion, blast_row, blast_col);
+            let (r0, c0, radius) = (blast_row as i64, blast_col as i64, radius as i64);
+            for r in (r0 - radius).max(0)..=(r0 + radius).min(self.height as i64 - 1) {
+                for c in (c0 - radius).max(0)..=(c0 + radius).min(self.width as i64 - 1) {
+                    if (r - r0) * (r - r0) + (c - c0) * (c - c0) > radius * radius {
+                        continue;
+                    }
+                    let idx = self.get_index(r as u32, c as u32);
+                    self.damage_rock(idx, BLAST_ROCK_DAMAGE);
+                    if self.cells[idx].id == CellType::PackedSnow {
+                        self.crumble_snow(r as u32, c as u32);
+                    }
+                    let cell = self.cells[idx];
+                    if cell.pinned || cell.phase() == Phase::Dead || cell.phase() == Phase::Immovable {
+                        continue;
+                    }
+                    if cell.id == CellType::Nitro && (r, c) != (r0, c0) {
+                        blasts.push((r as u32, c as u32));
+                    }
+                    if cell.id == CellType::Methane {
+                        self.flash_burn(r as u32, c as u32);
+                        continue;
+                    }
+                    self.sanction(cell, -1);
+                    self.cells[idx] = Cell::new(CellType::Dead);
+                    self.make_sound(SoundKind::Rumble, 1, blast_row, blast_col);
+                }
+            }
+        }
+    }
+
+    /// Burns away the whole pocket of methane connected to a position in one go
+    fn flash_burn(&mut self, row: u32, col: u32) {
+        self.push_event(EventKind::FlashBurn, row, col);
+        let mut stack = vec![(row, col)];
+        while let Some((r, c)) = stack.pop() {
+            let idx = self.get_index(r, c);
+            let cell = self.cells[idx];
+            if cell.id != CellType::Methane || cell.pinned {
+                continue;
+            }
+            self.sanction(cell, -1);
+            self.cells[idx] = Cell::new(CellType::Dead);
+            stack.extend(self.orthogonal_neighbors(r, c));
+        }
+    }
+
+    fn update_tar(&mut self, row: u32, col: u32) {
+        if self.random() < TAR_FLOW_CHANCE {
+            self.update_liquid(row, col, 1);
+        } else {
+            let idx = self.get_index(row, col);
+            self.mark_updated(idx);
+        }
+    }
+
+    /// Wet concrete flows like a thick liquid and sets into rock once it has rested for
+    /// `CONCRETE_CURE_TICKS`. It is too thick to pick up speed, so the energy field instead
+    /// counts the ticks since its activity faded away, and moving starts the count over.
+    fn update_wet_concrete(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        let cured = if self.cells[idx].activity == 0 { self.cells[idx].energy + 1 } else { 0 };
+        self.cells[idx].energy = 0;
+        if self.random() < CONCRETE_FLOW_CHANCE {
+            // It only spreads sideways under the weight of something on top of it
+            let (above_row, above_col) = self.toward_gravity(row, col, -1, 0);
+            let pressed = self
+                .resolve_position(above_row, above_col)
+                .is_some_and(|(r, c)| self.cells[self.get_index(r, c)].id != CellType::Dead);
+            self.update_liquid(row, col, if pressed { 1 } else { 0 });
+        } else {
+            self.mark_updated(idx);
+        }
+        let still_resting = self.cells[idx].id == CellType::WetConcrete && self.cells[idx].activity == 0;
+        if still_resting && cured >= CONCRETE_CURE_TICKS {
+            self.transmute(idx, CellType::Rock);
+            self.mark_updated(idx);
+        } else if still_resting {
+            self.cells[idx].energy = cured;
+        }
+    }
+
+    /// Shared liquid movement, `spread` is how far the liquid can flow sideways in one tick.
+    /// Energy follows the same rules as `update_sand`.
+    fn update_liquid(&mut self, row: u32, col: u32, spread: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        let cell_energy = self.cells[idx].energy;
+        let fall_distance = (cell_energy + 1).min(self.max_fall_distance);
+
+        let downwards_positions: Vec<_> =
+            (1..=fall_distance as i64).map(|i| self.toward_gravity(row, col, i, 0)).collect();
+        let left_down_positions = vec![self.toward_gravity(row, col, 1, -1)];
+        let right_down_positions = vec![self.toward_gravity(row, col, 1, 1)];
+        let left_positions: Vec<_> = (1..=spread as i64).map(|i| self.toward_gravity(row, col, 0, -i)).collect();
+        let right_positions: Vec<_> = (1..=spread as i64).map(|i| self.toward_gravity(row, col, 0, i)).collect();
+
+        // Liquids only sink straight down through lighter fluids, sideways they need room
+        let density = self.cells[idx].density();
+        let empty_downwards_positions = self.find_valid_positions_sinking(downwards_positions, density);
+        let empty_side_positions = self.find_valid_side_positions(left_positions, right_positions, None);
+        let empty_side_down_positions =
+            self.find_valid_side_positions(left_down_positions, right_down_positions, None);
+
+        if let Some(down_pos) = empty_downwards_positions.last() {
+            self.accelerate(idx);
+            let new_idx = self.get_index(down_pos.0, down_pos.1);
+            self.switch_cells(idx, new_idx);
+        } else if let Some(side_down_pos) = empty_side_down_positions.last() {
+            let new_idx = self.get_index(side_down_pos.0, side_down_pos.1);
+            self.switch_cells(idx, new_idx);
+        } else {
+            if cell_energy > 0 {
+                self.make_sound(SoundKind::Splash, 1, row, col);
+            }
+            self.strike_below(row, col);
+            self.cells[idx].energy = 0; // Spreading out on a surface isn't falling
+            if let Some(side_pos) = empty_side_positions.last() {
+                let new_idx = self.get_index(side_pos.0, side_pos.1);
+                self.switch_cells(idx, new_idx);
+            }
+        }
+    }
+
+    /// Acid flows like water, but first it may eat a sand or rock cell it touches, using
+    /// itself up. The cell below is tried first so acid eats its way down through a floor,
+    /// below being wherever gravity pulls.
+    fn update_acid(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        if self.random() < ACID_DISSOLVE_CHANCE {
+            let first_side = if self.prefers_right() { 1 } else { -1 };
+            let neighbors = [
+                self.toward_gravity(row, col, 1, 0),
+                self.toward_gravity(row, col, 0, first_side),
+                self.toward_gravity(row, col, 0, -first_side),
+                self.toward_gravity(row, col, -1, 0),
+            ];
+            let target = neighbors.iter().find_map(|&(r, c)| {
+                self.resolve_position(r, c).filter(|&(r, c)| {
+                    let cell = self.cells[self.get_index(r, c)];
+                    matches!(cell.id, CellType::Sand | CellType::Rock) && !cell.pinned
+                })
+            });
+            if let Some((r, c)) = target {
+                let target_idx = self.get_index(r, c);
+                self.transmute(target_idx, CellType::Dead);
+                self.transmute(idx, CellType::Dead);
+                self.mark_updated(idx);
+                return;
+            }
+        }
+        self.update_liquid(row, col, SPREAD_FACTOR);
+    }
+
+    /// Quicksand falls like sand, but a denser solid resting on top of it is slowly
+    /// pulled down through it. Only denser cells sink, so a trapped cell ends up under
+    /// the quicksand instead of cycling.
+    fn update_quicksand(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        let (above_row, above_col) = self.toward_gravity(row, col, -1, 0);
+        if let Some((above_row, above_col)) = self.is_phase(above_row, above_col, Phase::Solid) {
+            let above_idx = self.get_index(above_row, above_col);
+            let is_denser = self.cells[above_idx].density() > self.cells[idx].density();
+            if is_denser && self.random() < QUICKSAND_SINK_CHANCE {
+                self.mark_updated(idx);
+                self.mark_updated(above_idx);
+                self.cells[above_idx].energy = 0; // The trapped cell is slowed down to a crawl
+                self.switch_cells(idx, above_idx);
+                self.push_event(EventKind::Swallowed, row, col);
+                return;
+            }
+        }
+        self.update_sand(row, col);
+    }
+
+    /// Vines creep upwards and sideways into empty cells that lean against a wall.
+    /// The energy field holds how far the cell is from where the vine was planted.
+    fn update_vine(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        let length = self.cells[idx].energy;
+        if length >= VINE_MAX_LENGTH || self.random() > VINE_GROWTH_CHANCE {
+            return;
+        }
+
+        let candidates = [
+            (row.wrapping_sub(1), col),
+            (row.wrapping_sub(1), col.wrapping_sub(1)),
+            (row.wrapping_sub(1), col + 1),
+            (row, col.wrapping_sub(1)),
+            (row, col + 1),
+        ];
+        let growable: Vec<_> = candidates
+            .iter()
+            .filter_map(|x| self.is_empty_and_inbound(x.0, x.1))
+            .filter(|&(r, c)| {
+                // Only grow along walls, and keep the vine one cell thick
+                let leans_on_wall = self.orthogonal_neighbors(r, c).any(|(nr, nc)| {
+                    let neighbor = self.cells[self.get_index(nr, nc)];
+                    neighbor.phase() == Phase::Immovable && neighbor.id != CellType::Vine
+                });
+                leans_on_wall && self.count_neighbors(r, c, CellType::Vine) <= 1
+            })
+            .collect();
+        if growable.is_empty() {
+            return;
+        }
+
+        let (new_row, new_col) = growable[(self.random() * growable.len() as f64) as usize];
+        let new_idx = self.get_index(new_row, new_col);
+        self.cells[new_idx] = Cell {
+            energy: length + 1,
+            update_stamp: self.update_stamp,
+            ..Cell::new(CellType::Vine)
+        };
+        self.sanction(self.cells[new_idx], 1);
+    }
+
+    /// Geysers sit quiet for a while and then erupt, throwing water upwards every tick.
+    /// The energy field holds how far into its cycle the geyser is.
+    fn update_geyser(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        let phase = self.cells[idx].energy;
+        self.cells[idx].energy = (phase + 1) % (GEYSER_DORMANT_TICKS + GEYSER_ERUPTION_TICKS);
+        if phase < GEYSER_DORMANT_TICKS {
+            return;
+        }
+        if phase == GEYSER_DORMANT_TICKS {
+            self.push_event(EventKind::Eruption, row, col);
+        }
+
+        let jet_positions: Vec<_> = (1..=GEYSER_JET_HEIGHT)
+            .map(|i| (row.wrapping_sub(i), col))
+            .collect();
+        if let Some(jet_pos) = self.find_valid_positions(jet_positions).last() {
+            let new_idx = self.get_index(jet_pos.0, jet_pos.1);
+            self.cells[new_idx] = Cell {
+                update_stamp: self.update_stamp,
+                ..Cell::new(CellType::Water)
+            };
+            self.sanction(self.cells[new_idx], 1);
+        }
+    }
+
+    /// Dust floats down slowly and drifts, it is light enough to rest on liquids and gets
+    /// thrown back into the air when something lands hard next to it
+    fn update_dust(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        let (left, right) = (col.wrapping_sub(1), col + 1);
+        let (first_side, second_side) = if self.prefers_right() { (right, left) } else { (left, right) };
+
+        let positions = if self.is_disturbed(row, col) {
+            vec![(row.wrapping_sub(1), col), (row.wrapping_sub(1), first_side)]
+        } else if self.random() < DUST_FALL_CHANCE {
+            vec![(row + 1, col), (row + 1, first_side), (row + 1, second_side)]
+        } else if self.random() < DUST_DRIFT_CHANCE {
+            vec![(row, first_side)]
+        } else {
+            return;
+        };
+        if let Some(pos) = positions.iter().find_map(|x| self.is_empty_and_inbound(x.0, x.1)) {
+            let new_idx = self.get_index(pos.0, pos.1);
+            self.switch_cells(idx, new_idx);
+        }
+    }
+
+    /// Frost crawls over water, freezing neighbouring water cells into more frost. Frost
+    /// that has spread as far as it can, or that has frozen everything around it, hardens
+    /// into ice. The energy field holds how far the frost is from where it was placed.
+    fn update_frost(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        let distance = self.cells[idx].energy;
+        let water: Vec<_> = self
+            .orthogonal_neighbors(row, col)
+            .filter(|&(r, c)| {
+                let cell = self.cells[self.get_index(r, c)];
+                cell.id == CellType::Water && !cell.pinned && self.is_simulated(r, c)
+            })
+            .collect();
+        if distance >= FROST_MAX_SPREAD || (distance > 0 && water.is_empty()) {
+            self.freeze(idx, CellType::Ice, distance);
+            return;
+        }
+        if water.is_empty() || self.random() >= FROST_SPREAD_CHANCE {
+            return;
+        }
+
+        let (new_row, new_col) = water[(self.random() * water.len() as f64) as usize];
+        let new_idx = self.get_index(new_row, new_col);
+        self.freeze(new_idx, CellType::Frost, distance + 1);
+    }
+
+    /// Turns a cell into frost or ice at the given distance from where the frost started
+    fn freeze(&mut self, idx: usize, ct: CellType, distance: u32) {
+        self.sanction(self.cells[idx], -1);
+        self.cells[idx] = Cell {
+            energy: distance,
+            update_stamp: self.update_stamp,
+            ..Cell::new(ct)
+        };
+        self.sanction(self.cells[idx], 1);
+    }
+
+    /// Methane rises and spreads out under whatever ceiling it reaches, so it collects in
+    /// pockets. Explosions set it off.
+    fn update_methane(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        self.rise(row, col);
+    }
+
+    /// Steam rises like methane until its energy field, the ticks it has left, runs out.
+    /// It then either condenses back into water or disappears.
+    fn update_steam(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        let life = self.cells[idx].energy.saturating_sub(1);
+        if life == 0 {
+            let condensed = if self.random() < STEAM_CONDENSE_CHANCE { CellType::Water } else { CellType::Dead };
+            self.transmute(idx, condensed);
+            self.mark_updated(idx);
+            return;
+        }
+        self.cells[idx].energy = life;
+        self.rise(row, col);
+    }
+
+    /// Moves a gas one cell up, diagonally up or sideways into an empty cell, in that order
+    fn rise(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        let (left, right) = (col.wrapping_sub(1), col + 1);
+        let (first_side, second_side) = if self.prefers_right() { (right, left) } else { (left, right) };
+        let above = row.wrapping_sub(1);
+        let positions = [
+            (above, col),
+            (above, first_side),
+            (above, second_side),
+            (row, first_side),
+            (row, second_side),
+        ];
+        if let Some(pos) = positions.iter().find_map(|x| self.is_empty_and_inbound(x.0, x.1)) {
+            let new_idx = self.get_index(pos.0, pos.1);
+            self.switch_cells(idx, new_idx);
+        }
+    }
+
+    /// Fire flickers upwards and burns out, the energy field counts down the ticks it has
+    /// left. Flammable cells it touches, like oil and vines, may catch fire, methane it
+    /// touches goes up all at once and water it touches now and then boils into steam.
+    /// The heat it gives off also boils water further away, see `diffuse_heat`.
+    fn update_fire(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        let neighbors: Vec<_> = self.orthogonal_neighbors(row, col).collect();
+        for (r, c) in neighbors {
+            let neighbor_idx = self.get_index(r, c);
+            let neighbor = self.cells[neighbor_idx];
+            if neighbor.pinned || !self.is_simulated(r, c) {
+                continue;
+            }
+            match neighbor.id {
+                CellType::Methane => self.flash_burn(r, c),
+                // A flame rises away before its heat soaks far into the water, so the water
+                // right next to it is boiled directly
+                CellType::Water if self.random() < BOIL_CHANCE => {
+                    self.transmute(neighbor_idx, CellType::Steam);
+                    self.mark_updated(neighbor_idx);
+                }
+                _ if neighbor.flammability() > 0.0 && self.random() < neighbor.flammability() => {
+                    // A fresh flame only spreads on the next tick, so a pool of oil burns
+                    // one cell further every tick instead of all at once
+                    self.transmute(neighbor_idx, CellType::Fire);
+                    self.mark_updated(neighbor_idx);
+                }
+                _ => (),
+            }
+        }
+
+        let life = self.cells[idx].energy.saturating_sub(1);
+        if life == 0 {
+            self.transmute(idx, CellType::Dead);
+            return;
+        }
+        self.cells[idx].energy = life;
+        let (left, right) = (col.wrapping_sub(1), col + 1);
+        let (first_side, second_side) = if self.prefers_right() { (right, left) } else { (left, right) };
+        let above = row.wrapping_sub(1);
+        let positions = [(above, col), (above, first_side), (above, second_side)];
+        if let Some(pos) = positions.iter().find_map(|x| self.is_empty_and_inbound(x.0, x.1)) {
+            let new_idx = self.get_index(pos.0, pos.1);
+            self.switch_cells(idx, new_idx);
+        }
+    }
+
+    /// Clouds float up to the top of the grid and drift along it, now and then dropping
+    /// water into the empty cell below them
+    fn update_cloud(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        let raining = self.random() < self.cloud_rain_rate;
+        if let Some((drop_row, drop_col)) = self.is_empty_and_inbound(row + 1, col).filter(|_| raining) {
+            let drop_idx = self.get_index(drop_row, drop_col);
+            self.cells[drop_idx] = Cell {
+                update_stamp: self.update_stamp,
+                ..Cell::new(CellType::Water)
+            };
+            self.sanction(self.cells[drop_idx], 1);
+        }
+        if self.random() >= self.cloud_drift_rate {
+            return;
+        }
+
+        let side = if self.prefers_right() { col + 1 } else { col.wrapping_sub(1) };
+        let positions = [(row.wrapping_sub(1), col), (row, side)];
+        if let Some(pos) = positions.iter().find_map(|x| self.is_empty_and_inbound(x.0, x.1)) {
+            let new_idx = self.get_index(pos.0, pos.1);
+            self.switch_cells(idx, new_idx);
+        }
+    }
+
+    /// Whether a fast moving cell is close enough to stir up dust at this position
+    fn is_disturbed(&self, row: u32, col: u32) -> bool {
+        let reach = DUST_DISTURB_RADIUS;
+        let rows = row.saturating_sub(reach)..=(row + reach).min(self.height - 1);
+        rows.flat_map(|r| {
+            let cols = col.saturating_sub(reach)..=(col + reach).min(self.width - 1);
+            cols.map(move |c| (r, c))
+        })
+        .any(|(r, c)| {
+            let cell = self.cells[self.get_index(r, c)];
+            cell.id != CellType::Dust && cell.energy >= DUST_DISTURB_ENERGY
+        })
+    }
+
+    /// Iron filings fall like sand unless a magnet is in range, then they are pulled one
+    /// cell towards the closest magnet instead and cling to it once they reach it
+    fn update_iron_filings(&mut self, row: u32, col: u32) {
+        let magnet = self.closest_magnet(row, col);
+        let (magnet_row, magnet_col) = match magnet {
+            Some(pos) if self.random() < MAGNET_STRENGTH => pos,
+            _ => return self.update_sand(row, col),
+        };
+        self.pull_toward(row, col, magnet_row, magnet_col);
+    }
+
+    /// Moves a cell one step towards a target, diagonally if it can and straight along
+    /// one axis otherwise. The cell loses its falling energy either way.
+    fn pull_toward(&mut self, row: u32, col: u32, target_row: u32, target_col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx);
+        self.cells[idx].energy = 0;
+
+        let step = |from: u32, to: u32| match to.cmp(&from) {
+            Ordering::Less => from.wrapping_sub(1),
+            Ordering::Equal => from,
+            Ordering::Greater => from + 1,
+        };
+        let (pull_row, pull_col) = (step(row, target_row), step(col, target_col));
+        let positions = [(pull_row, pull_col), (pull_row, col), (row, pull_col)];
+        if let Some(pos) = positions.iter().find_map(|x| self.is_empty_and_inbound(x.0, x.1)) {
+            let new_idx = self.get_index(pos.0, pos.1);
+            self.switch_cells(idx, new_idx);
+        }
+    }
+
+    /// Pulls a movable cell towards the closest gravity well it is inside of, returns
+    /// whether the well took over the cell's move this tick
+    fn fall_into_well(&mut self, row: u32, col: u32) -> bool {
+        let distance = |well: &GravityWell| {
+            let (dr, dc) = (well.row as i64 - row as i64, well.col as i64 - col as i64);
+            dr * dr + dc * dc
+        };
+        let well = self
+            .gravity_wells
+            .iter()
+            .filter(|well| distance(well) <= well.radius as i64 * well.radius as i64)
+            .min_by_key(|well| distance(well))
+            .copied();
+        match well {
+            Some(well) if self.random() < well.strength => {
+                self.pull_toward(row, col, well.row, well.col);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn closest_magnet(&self, row: u32, col: u32) -> Option<(u32, u32)> {
+        let rows = row.saturating_sub(MAGNET_RADIUS)..=(row + MAGNET_RADIUS).min(self.height - 1);
+        rows.flat_map(|r| {
+            let cols = col.saturating_sub(MAGNET_RADIUS)..=(col + MAGNET_RADIUS).min(self.width - 1);
+            cols.map(move |c| (r, c))
+        })
+        .filter(|&(r, c)| self.cells[self.get_index(r, c)].id == CellType::Magnet)
+        .min_by_key(|&(r, c)| {
+            let (dr, dc) = (r as i64 - row as i64, c as i64 - col as i64);
+            dr * dr + dc * dc
+        })
+    }
+
+    fn update_rock(&mut self, row: u32, col: u32) {
+        let idx = self.get_index(row, col);
+        self.mark_updated(idx)
+    }
+}
+
+
+/// Public methods, exported to JavaScript.
+#[wasm_bindgen]
+impl Universe {
+    pub fn tick(&mut self) {
+        if self.paused {
+            return;
+        }
+        #[cfg(feature = "conservation-check")]
+        {
+            self.conservation.conserved = true;
+        }
+        // The active layer goes last, so the conservation amounts are the ones it ended with
+        for layer in 0..self.layers.len() {
+            if layer != self.active_layer {
+                self.swap_layer(layer);
+                self.step();
+                self.swap_layer(layer);
+            }
+        }
+        self.step();
+        self.sound_off();
+        self.generation += 1;
+        self.tick_parity = !self.tick_parity;
+    }
+
+    /// Runs `steps` ticks in one call, exactly like calling `tick()` that many times
+    pub fn tick_n(&mut self, steps: u32) {
+        for _ in 0..steps {
+            self.tick();
+        }
+    }
+
+    /// Adds an empty layer called `name` on top of the others, returns false if there
+    /// already is one by that name. Layers tick independently with the same settings
+    /// and never exchange cells. Until the first layer is added the grid is the only
+    /// one, after that it is the bottom layer called "base".
+    pub fn add_layer(&mut self, name: &str) -> bool {
+        if self.layers.is_empty() {
+            self.layers.push(Layer {
+                name: BASE_LAYER.to_string(),
+                cells: Vec::new(),
+                temperature: Vec::new(),
+                swap_streaks: HashMap::new(),
+                update_stamp: 1,
+            });
+            self.active_layer = 0;
+        }
+        if self.layers.iter().any(|layer| layer.name == name) {
+            return false;
+        }
+        self.layers.push(Layer {
+            name: name.to_string(),
+            cells: vec![Cell::new(CellType::Dead); self.cells.len()],
+            temperature: vec![AMBIENT_TEMPERATURE; self.cells.len()],
+            swap_streaks: HashMap::new(),
+            update_stamp: 1,
+        });
+        true
+    }
+
+    /// Makes the layer called `name` the one painting, queries and exports work on,
+    /// returns false if there is no such layer
+    pub fn set_active_layer(&mut self, name: &str) -> bool {
+        let layer = match self.layers.iter().position(|layer| layer.name == name) {
+            Some(layer) => layer,
+            None => return false,
+        };
+        if layer != self.active_layer {
+            self.swap_layer(self.active_layer);
+            self.swap_layer(layer);
+            self.active_layer = layer;
+        }
+        true
+    }
+
+    /// The cell ids of all layers stacked over each other, row by row. Each position
+    /// shows the topmost layer that isn't empty there.
+    pub fn composite_cells(&self) -> Vec<u8> {
+        let mut ids: Vec<u8> = self.cells.iter().map(|c| c.id as u8).collect();
+        if self.layers.is_empty() {
+            return ids;
+        }
+        ids.fill(CellType::Dead as u8);
+        for (index, layer) in self.layers.iter().enumerate() {
+            let cells = if index == self.active_layer { &self.cells } else { &layer.cells };
+            for (id, cell) in ids.iter_mut().zip(cells) {
+                if cell.id != CellType::Dead {
+                    *id = cell.id as u8;
+                }
+            }
+        }
+        ids
+    }
+
+    /// Whether the last tick neither created nor destroyed any material outside of
+    /// reactions such as explosions, geysers and growing vines
+    #[cfg(feature = "conservation-check")]
+    pub fn verify_conservation(&self) -> bool {
+        self.conservation.conserved
+    }
+
+    /// Replaces every random number the simulation draws with `values` divided by 2^32,
+    /// in order and starting over after the last one, so tests can force any chance to
+    /// pass or fail. An empty sequence goes back to the seeded generator. Only this
+    /// universe is affected.
+    #[cfg(feature = "scripted-rng")]
+    pub fn set_rng_sequence(&mut self, values: &[u32]) {
+        self.rng.set_sequence(values);
+    }
+
+    /// Particle id of every cell, row by row. Every non-empty cell keeps its id for as long
+    /// as it exists, empty cells are 0.
+    #[cfg(feature = "particle-ids")]
+    pub fn particle_ids(&self) -> Vec<u32> {
+        self.cells.iter().map(|c| c.particle).collect()
+    }
+
+    /// Amount of every element after the last tick, indexed by `CellType`. Water is
+    /// measured by its total fill, everything else by cell count.
+    #[cfg(feature = "conservation-check")]
+    pub fn conserved_amounts(&self) -> Vec<i64> {
+        self.conservation.amounts.clone()
+    }
+
+    /// Runs one tick like `tick()` while recording every move that happens during it,
+    /// for tracking down why a configuration behaves oddly
+    pub fn tick_probe(&mut self) -> Vec<ProbeRecord> {
+        self.probe = Some(Vec::new());
+        self.tick();
+        self.probe.take().unwrap_or_default()
+    }
+
+    /// Makes `tick()` queue a `Splash` or `Rumble` event whenever at least `value` of that
+    /// kind happened during the tick, 0 turns the sound off. A tick queues at most one
+    /// event per kind, placed where the last contribution to it happened.
+    pub fn set_sound_threshold(&mut self, kind: SoundKind, value: u32) {
+        self.sound_meters[kind as usize].threshold = value;
+    }
+
+    /// Hands over every event queued since the last call and clears the queue
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// FNV-1a hash of every cell's type, energy and fill. Two runs given the same inputs
+    /// should hash the same after every tick, so comparing hashes shows where runs diverge.
+    pub fn hash_state(&self) -> u64 {
+        let mut hash = FNV_OFFSET_BASIS;
+        for cell in &self.cells {
+            let energy = cell.energy.to_le_bytes();
+            let bytes = [cell.id as u8, energy[0], energy[1], energy[2], energy[3], cell.fill];
+            for &byte in &bytes {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        }
+        hash
+    }
+
+    /// Runs `steps` ticks and returns the cell ids afterwards, row by row, in a single
+    /// call. This really advances the universe, it is not a dry run.
+    pub fn run_and_snapshot(&mut self, steps: u32) -> Vec<u8> {
+        self.tick_n(steps);
+        self.cell_ids().map(|ct| ct as u8).collect()
+    }
+
+    /// Stops or resumes the physics, `tick()` does nothing while paused
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// How many ticks have been run since the universe was created
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Approximate heap footprint of the universe in bytes, counted from the capacity of every
+    /// internal buffer. Hash maps are estimated from their capacity and entry size.
+    pub fn memory_bytes(&self) -> usize {
+        use std::mem::size_of;
+        let streaks_bytes = |streaks: &HashMap<(usize, usize), SwapStreak>| {
+            streaks.capacity() * (size_of::<(usize, usize)>() + size_of::<SwapStreak>())
+        };
+        let layers_bytes: usize = self
+            .layers
+            .iter()
+            .map(|layer| {
+                layer.name.capacity()
+                    + layer.cells.capacity() * size_of::<Cell>()
+                    + layer.temperature.capacity() * size_of::<f32>()
+                    + streaks_bytes(&layer.swap_streaks)
+            })
+            .sum();
+        #[allow(unused_mut)]
+        let mut bytes = size_of::<Universe>()
+            + self.cells.capacity() * size_of::<Cell>()
+            + self.id_buffer.capacity() * size_of::<CellType>()
+            + self.energy_buffer.capacity() * size_of::<u32>()
+            + self.temperature.capacity() * size_of::<f32>()
+            + self.update_intervals.capacity() * size_of::<u32>()
+            + self.probe.as_ref().map_or(0, |records| records.capacity() * size_of::<ProbeRecord>())
+            + self.events.capacity() * size_of::<Event>()
+            + self.sound_meters.capacity() * size_of::<SoundMeter>()
+            + self.reactions.capacity() * size_of::<Reaction>()
+            + self.gravity_wells.capacity() * size_of::<GravityWell>()
+            + self.level_regulators.capacity() * size_of::<LevelRegulator>()
+            + streaks_bytes(&self.swap_streaks)
+            + self.held.capacity() * size_of::<usize>()
+            + self.layers.capacity() * size_of::<Layer>()
+            + layers_bytes;
+        #[cfg(feature = "conservation-check")]
+        {
+            bytes += (self.conservation.amounts.capacity()
+                + self.conservation.sanctioned.capacity())
+                * size_of::<i64>();
+        }
+        bytes
+    }
+
+    /// Runs only the liquid movement until the liquids have settled or `max_iterations`
+    /// passes have been made, returns how many passes were made. Tar, surface tension and
+    /// pressure only move liquid by chance, so a pass where nothing moved isn't enough:
+    /// no liquid may have room to flow into, and nothing may move for
+    /// `SETTLE_QUIET_PASSES` passes in a row.
+    pub fn settle_liquids(&mut self, max_iterations: u32) -> u32 {
+        let mut quiet_passes = 0;
+        for iteration in 0..max_iterations {
+            let before: Vec<CellType> = self.cell_ids().collect();
+            for (row, col) in self.gravity_order() {
+                let idx = self.get_index(row, col);
+                let cell = self.cells[idx];
+                if self.is_updated(&cell) || cell.pinned {
+                    continue;
+                }
+                match cell.id {
+                    CellType::Water => self.update_water(row, col),
+                    CellType::Tar => self.update_tar(row, col),
+                    CellType::WetConcrete => self.update_liquid(row, col, 1),
+                    CellType::Oil | CellType::Acid => self.update_liquid(row, col, SPREAD_FACTOR),
+                    CellType::Slime => self.update_slime(row, col),
+                    CellType::Nitro => self.update_nitro(row, col),
+                    _ => (),
+                }
+            }
+            self.reset_updated();
+
+            let moved = before.iter().zip(self.cell_ids()).any(|(&before_id, id)| before_id != id);
+            quiet_passes = if moved || !self.liquids_stable() { 0 } else { quiet_passes + 1 };
+            if quiet_passes == SETTLE_QUIET_PASSES {
+                return iteration + 1;
+            }
+        }
+        max_iterations
+    }
+
+    /// Runs only the powder movement until no powder moves or `max_iterations` passes
+    /// have been made, returns how many passes were made. Every powder falls like sand
+    /// here, so floating dust and filings near a magnet drop straight onto the pile too.
+    pub fn settle_powders(&mut self, max_iterations: u32) -> u32 {
+        for iteration in 0..max_iterations {
+            let before: Vec<CellType> = self.cell_ids().collect();
+            for (row, col) in self.gravity_order() {
+                let idx = self.get_index(row, col);
+                let cell = self.cells[idx];
+                if self.is_updated(&cell) || cell.pinned || cell.phase() != Phase::Solid {
+                    continue;
+                }
+                self.update_sand(row, col);
+            }
+            self.reset_updated();
+
+            let moved = before.iter().zip(self.cell_ids()).any(|(&before_id, id)| before_id != id);
+            if !moved {
+                return iteration + 1;
+            }
+        }
+        max_iterations
+    }
+
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Universe {
+        Universe::new_with_size(WIDTH, HEIGHT)
+    }
+
+    /// An empty universe of the given size, clamped like `set_width` and `set_height`
+    pub fn new_with_size(width: u32, height: u32) -> Universe {
+        Universe::new_seeded(width, height, utils::random_seed())
+    }
+
+    /// Like `new_with_size`, but every random number comes from a generator started with
+    /// `seed`. Two universes with the same seed given the same calls end up identical.
+    pub fn new_seeded(width: u32, height: u32, seed: u64) -> Universe {
+        utils::set_panic_hook(); // If our code panics, we want informative error messages to appear in the developer console
+
+        let width = Universe::clamp_dimension(width, 1);
+        let height = Universe::clamp_dimension(height, width);
+
+        let cells = (0..width * height)
+            .map(|_i| Cell::new(CellType::Dead))
+            .collect();
+        let temperature = vec![AMBIENT_TEMPERATURE; (width * height) as usize];
+
+        Universe {
+            width,
+            height,
+            cells,
+            id_buffer: Vec::new(),
+            energy_buffer: Vec::new(),
+            temperature,
+            scan_mode: ScanMode::Ordered,
+            chunked: true,
+            max_fall_distance: u32::MAX,
+            terminal_velocity: u32::MAX,
+            slide_reach: 1,
+            rock_durability: 0,
+            packed_snow_strength: DEFAULT_PACKED_SNOW_STRENGTH,
+            rubber_restitution: DEFAULT_RUBBER_RESTITUTION,
+            update_intervals: vec![1; all_elements().len()],
+            generation: 0,
+            paused: false,
+            erosion_rate: DEFAULT_EROSION_RATE,
+            surface_tension: DEFAULT_SURFACE_TENSION,
+            cloud_drift_rate: DEFAULT_CLOUD_DRIFT_RATE,
+            cloud_rain_rate: DEFAULT_CLOUD_RAIN_RATE,
+            probe: None,
+            side_bias: 0.5,
+            activity_decay: DEFAULT_ACTIVITY_DECAY,
+            events: Vec::new(),
+            sound_meters: vec![SoundMeter::default(); 2],
+            reactions: Vec::new(),
+            gravity_wells: Vec::new(),
+            level_regulators: Vec::new(),
+            oscillation_limit: DEFAULT_OSCILLATION_LIMIT,
+            swap_streaks: HashMap::new(),
+            held: Vec::new(),
+            update_stamp: 1,
+            pass: 0,
+            layers: Vec::new(),
+            density_aware_paint: false,
+            active_layer: 0,
+            rain_rate: 0.0,
+            rain_type: CellType::Water,
+            active_bounds: None,
+            lightning_jaggedness: DEFAULT_LIGHTNING_JAGGEDNESS,
+            lightning_branching: DEFAULT_LIGHTNING_BRANCHING,
+            rng: Rng::new(seed),
+            wrap: false,
+            gravity: GravityDir::Down,
+            tick_parity: false,
+            #[cfg(feature = "conservation-check")]
+            conservation: Conservation {
+                amounts: Vec::new(),
+                sanctioned: Vec::new(),
+                conserved: true,
+            },
+            #[cfg(feature = "particle-ids")]
+            next_particle: 1,
+        }
+    }
+
+    /// A ready to run hourglass on the default grid: rock walls that pinch to a two cell
+    /// gap in the middle, with a block of sand in the top chamber
+    pub fn hourglass() -> Universe {
+        let mut universe = Universe::new();
+        let (width, height) = (universe.width, universe.height);
+        let (neck_row, left_wall, right_wall) = (height / 2, width / 2 - 2, width / 2 + 1);
+        // A wall one line thick would let sand slip out diagonally between its steps
+        for thickness in 0..2 {
+            universe.draw_line(thickness, 0, neck_row - 1 + thickness, left_wall, CellType::Rock);
+            universe.draw_line(thickness, width - 1, neck_row - 1 + thickness, right_wall, CellType::Rock);
+            universe.draw_line(neck_row + thickness, left_wall, height - 1 + thickness, 0, CellType::Rock);
+            universe.draw_line(neck_row + thickness, right_wall, height - 1 + thickness, width - 1, CellType::Rock);
+        }
+        universe.seal_borders(CellType::Rock, 1);
+        universe.fill_rect(height / 16, width / 4, width / 2, height / 6, CellType::Sand);
+        universe
+    }
+
+    /// Replaces the grid with a fixed worst case scene for benchmarking ticks, compare
+    /// `hash_state` afterwards to check an optimization didn't change the result.
+    ///
+    /// - 0: the whole grid in a checkerboard of sand and water, everything keeps moving
+    /// - 1: a column of sand a third of the grid wide from top to bottom collapsing
+    /// - 2: a dam break, the left half of the grid full of water
+    /// - 3: the top half full of sand, all of it falling at once
+    ///
+    /// Any other kind leaves the grid empty.
+    pub fn stress_scene(&mut self, kind: u32) {
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let ct = match kind {
+                    0 if (row + col) % 2 == 0 => CellType::Sand,
+                    0 => CellType::Water,
+                    1 if col >= self.width / 3 && col < self.width - self.width / 3 => CellType::Sand,
+                    2 if col < self.width / 2 => CellType::Water,
+                    3 if row < self.height / 2 => CellType::Sand,
+                    _ => CellType::Dead,
+                };
+                let idx = self.get_index(row, col);
+                self.cells[idx] = Cell::new(ct);
+            }
+        }
+    }
+
+    pub fn render_to_console(&self) -> String {
+        self.to_string()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
         self.height
     }
 
-    /// This method will be called by javascript to get the memory buffer of our cells
-    pub fn cells(&self) -> *const CellType {
-        self.cells
-            .iter()
-            .map(|&c| c.id)
-            .collect::<Vec<CellType>>()
-            .as_ptr()
+    /// This method will be called by javascript to get the memory buffer of our cells.
+    /// The types are copied into a buffer the universe keeps, so the pointer stays valid
+    /// and keeps pointing at the same buffer until the grid is resized.
+    pub fn cells(&mut self) -> *const CellType {
+        self.id_buffer.clear();
+        self.id_buffer.extend(self.cells.iter().map(|c| c.id));
+        self.id_buffer.as_ptr()
+    }
+
+    /// Like `cells()`, but for the energy of every cell. For falling cells that is how
+    /// fast they are falling, so javascript can draw motion blur or tint fast sand.
+    pub fn energies(&mut self) -> *const u32 {
+        self.energy_buffer.clear();
+        self.energy_buffer.extend(self.cells.iter().map(|c| c.energy));
+        self.energy_buffer.as_ptr()
+    }
+
+    pub fn set_scan_mode(&mut self, mode: ScanMode) {
+        self.scan_mode = mode;
+    }
+
+    /// Only simulates the cells from (r0, c0) to (r1, c1), both corners included, leaving
+    /// everything else frozen. Cells inside treat the edge of the window like the edge of
+    /// the grid.
+    pub fn set_active_bounds(&mut self, r0: u32, c0: u32, r1: u32, c1: u32) {
+        self.active_bounds = Some((r0.min(r1), c0.min(c1), r0.max(r1), c0.max(c1)));
+    }
+
+    /// Which way sand, powders, liquids and slime fall, along with the helpers built on
+    /// falling: quicksand swallows what lands on it, acid eats downwards first, wet
+    /// concrete is pressed by what lies on it, density aware painting sinks cells, and
+    /// `is_stable`, `settle_liquids` and `settle_powders` all follow it. Gases, dust and
+    /// the other elements with their own movement keep going by the screen.
+    pub fn set_gravity(&mut self, dir: GravityDir) {
+        self.gravity = dir;
+    }
+
+    /// Makes the grid wrap around like a torus: sand falling off the bottom comes back
+    /// in at the top and water flowing off the right edge comes back on the left. Heat,
+    /// fire and reactions reach across the edges as well. Off, the edges are walls.
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+    }
+
+    /// Goes back to simulating the whole grid after `set_active_bounds`
+    pub fn clear_active_bounds(&mut self) {
+        self.active_bounds = None;
+    }
+
+    /// Whether `tick()` skips chunks of the grid that hold nothing but empty space, pinned
+    /// cells and inert elements like rock, and the rows outside of the band the other
+    /// chunks span. The result is the same either way, turning it off only makes ticks on
+    /// big, mostly empty grids slower.
+    pub fn set_chunked(&mut self, enabled: bool) {
+        self.chunked = enabled;
+    }
+
+    /// Adds a rule that turns touching `a` and `b` cells into `out_a` and `out_b`, with
+    /// `probability` being the chance per tick that a touching pair reacts. The first rule
+    /// added for a pair wins.
+    pub fn add_reaction(&mut self, a: CellType, b: CellType, out_a: CellType, out_b: CellType, probability: f64) {
+        self.reactions.push(Reaction {
+            a,
+            b,
+            out_a,
+            out_b,
+            probability: probability.clamp(0.0, 1.0),
+        });
+    }
+
+    /// Removes every rule added with `add_reaction`
+    pub fn clear_reactions(&mut self) {
+        self.reactions.clear();
+    }
+
+    /// How many passes in a row two cells may keep swapping places back and forth before
+    /// the one that just moved is held still for a pass. 0, the default, never holds
+    /// anything and skips the bookkeeping for it.
+    pub fn set_oscillation_limit(&mut self, limit: u32) {
+        self.oscillation_limit = limit;
+    }
+
+    /// Adds a well that pulls movable cells within `radius` of it towards its center
+    /// instead of letting them fall. `strength` is the chance per tick that a cell in range
+    /// is pulled, clamped to [0, 1]. A cell inside several wells is pulled by the closest.
+    pub fn add_gravity_well(&mut self, row: u32, col: u32, radius: u32, strength: f64) {
+        self.gravity_wells.push(GravityWell { row, col, radius, strength: strength.clamp(0.0, 1.0) });
+    }
+
+    /// Removes every well added with `add_gravity_well`
+    pub fn clear_gravity_wells(&mut self) {
+        self.gravity_wells.clear();
+    }
+
+    /// Keeps columns `col_start` to `col_end` filled with `ct` up to `target_row`, adding
+    /// cells when the level drops and removing them above it, like a tap with an overflow.
+    /// At most `flow_rate` cells are added or removed per tick. Pinned cells are never
+    /// removed.
+    pub fn maintain_level(&mut self, col_start: u32, col_end: u32, target_row: u32, ct: CellType, flow_rate: u32) {
+        self.level_regulators.push(LevelRegulator {
+            col_start: col_start.min(col_end),
+            col_end: col_start.max(col_end),
+            target_row,
+            cell_type: ct,
+            flow_rate,
+        });
+    }
+
+    /// Removes every regulator added with `maintain_level`
+    pub fn clear_level_regulators(&mut self) {
+        self.level_regulators.clear();
+    }
+
+    /// Keeps spawning `ct` along the top row every tick, `rate` is how many cells per tick
+    /// and 0 stops the rain
+    pub fn set_rain(&mut self, rate: f64, ct: CellType) {
+        self.rain_rate = rate.max(0.0);
+        self.rain_type = ct;
+    }
+
+    /// Sends a bolt of lightning down from the top of `col`, zigzagging through empty
+    /// cells and gases and forking now and then. Each fork stops at the first solid or
+    /// liquid it hits or at the bottom row, setting off nitroglycerin it strikes and
+    /// setting flammable cells alight as readily as a flame would. Methane it passes
+    /// through burns up. Returns the indices of the cells the bolt went through
+    /// so javascript can draw the flash.
+    pub fn strike_lightning(&mut self, col: u32) -> Vec<u32> {
+        let mut path = Vec::new();
+        if col >= self.width {
+            return path;
+        }
+        let mut bolts = vec![(0, col)];
+        let mut branches = 0;
+        while let Some((mut row, mut col)) = bolts.pop() {
+            loop {
+                let idx = self.get_index(row, col);
+                let cell = self.cells[idx];
+                let passes = match cell.phase() {
+                    Phase::Dead | Phase::Gas => !cell.pinned,
+                    _ => false,
+                };
+                if !passes {
+                    self.push_event(EventKind::Lightning, row, col);
+                    if cell.id == CellType::Nitro && !cell.pinned {
+                        self.explode(row, col, NITRO_BLAST_RADIUS);
+                    } else if !cell.pinned && cell.flammability() > 0.0 && self.random() < cell.flammability() {
+                        self.transmute(idx, CellType::Fire);
+                    }
+                    break;
+                }
+                if cell.id == CellType::Methane {
+                    self.flash_burn(row, col);
+                }
+                path.push(idx as u32);
+                if row + 1 == self.height {
+                    self.push_event(EventKind::Lightning, row, col);
+                    break;
+                }
+                row += 1;
+                if self.random() < self.lightning_jaggedness {
+                    let side = if self.prefers_right() { col + 1 } else { col.wrapping_sub(1) };
+                    if side < self.width {
+                        col = side;
+                    }
+                }
+                if branches < MAX_LIGHTNING_BRANCHES && self.random() < self.lightning_branching {
+                    let side = if self.prefers_right() { col + 1 } else { col.wrapping_sub(1) };
+                    if side < self.width {
+                        branches += 1;
+                        bolts.push((row, side));
+                    }
+                }
+            }
+        }
+        path
+    }
+
+    /// Chance per row that a lightning bolt steps sideways, 0 draws straight bolts
+    pub fn set_lightning_jaggedness(&mut self, jaggedness: f64) {
+        self.lightning_jaggedness = jaggedness.clamp(0.0, 1.0);
+    }
+
+    /// Chance per row that a lightning bolt forks, 0 never forks
+    pub fn set_lightning_branching(&mut self, branching: f64) {
+        self.lightning_branching = branching.clamp(0.0, 1.0);
+    }
+
+    /// How strongly cells prefer sliding right over left: 0.5 is neutral, 1 always tries
+    /// right first and 0 always tries left first. Useful for faking a tilted surface.
+    pub fn set_side_bias(&mut self, bias: f64) {
+        self.side_bias = bias.clamp(0.0, 1.0);
+    }
+
+    /// Chance per tick that a small droplet holds its shape instead of spreading out,
+    /// 0 turns surface tension off
+    pub fn set_surface_tension(&mut self, tension: f64) {
+        self.surface_tension = tension.clamp(0.0, 1.0);
+    }
+
+    /// Chance per tick that fast moving water carries away a neighbouring sand grain,
+    /// 0 turns erosion off
+    pub fn set_erosion_rate(&mut self, rate: f64) {
+        self.erosion_rate = rate.clamp(0.0, 1.0);
+    }
+
+    /// How much the activity of a cell fades every tick, 255 keeps only the last tick
+    pub fn set_activity_decay(&mut self, decay: u8) {
+        self.activity_decay = decay;
+    }
+
+    /// Chance per tick that a cloud rises or drifts sideways by one cell
+    pub fn set_cloud_drift_rate(&mut self, rate: f64) {
+        self.cloud_drift_rate = rate.clamp(0.0, 1.0);
+    }
+
+    /// Chance per tick that a cloud rains a water cell below it, 0 keeps clouds dry
+    pub fn set_cloud_rain_rate(&mut self, rate: f64) {
+        self.cloud_rain_rate = rate.clamp(0.0, 1.0);
+    }
+
+    /// Caps how many cells a falling cell can move in one tick, whatever its energy.
+    /// 1 gives strict one cell per tick movement.
+    pub fn set_max_fall_distance(&mut self, n: u32) {
+        self.max_fall_distance = n.max(1);
+    }
+
+    /// Makes an element only update on every `every_n_ticks`th tick, so slow or mostly
+    /// settled materials cost less. Everything updates every tick by default.
+    pub fn set_update_interval(&mut self, ct: CellType, every_n_ticks: u32) {
+        self.update_intervals[ct as usize] = every_n_ticks.max(1);
+    }
+
+    /// Share of a landing cell's energy that rubber turns into bounce height, 0 stops
+    /// rubber from bouncing anything
+    pub fn set_rubber_restitution(&mut self, restitution: f64) {
+        self.rubber_restitution = restitution.clamp(0.0, 1.0);
+    }
+
+    /// How much damage from hard landings and explosions rock takes before crumbling into
+    /// sand, 0 (the default) makes rock indestructible
+    pub fn set_rock_durability(&mut self, durability: u32) {
+        self.rock_durability = durability;
+    }
+
+    /// Impact energy a falling cell needs to break packed snow it lands on. Explosions
+    /// always break it.
+    pub fn set_packed_snow_strength(&mut self, strength: u32) {
+        self.packed_snow_strength = strength;
+    }
+
+    /// How many cells down a steep slope sand can slide diagonally in one tick, 1 only
+    /// lets it move to the cells diagonally below
+    pub fn set_slide_reach(&mut self, reach: u32) {
+        self.slide_reach = reach.max(1);
+    }
+
+    /// Energy at which falling cells stop speeding up, as if air resistance balanced
+    /// gravity. Unlike `set_max_fall_distance` this also limits how hard cells hit things,
+    /// e.g. whether falling nitroglycerin goes off.
+    pub fn set_terminal_velocity(&mut self, energy: u32) {
+        self.terminal_velocity = energy;
+    }
+
+    /// Sets the width, keeping the cells that still fit and leaving new columns empty.
+    /// A width of 0 becomes 1, and widths that would make the grid larger than `MAX_CELLS`
+    /// are cut down to the largest one that fits.
+    pub fn set_width(&mut self, width: u32) {
+        self.resize(Universe::clamp_dimension(width, self.height), self.height);
+    }
+
+    /// Sets the height like `set_width`, new rows are added at the bottom
+    pub fn set_height(&mut self, height: u32) {
+        self.resize(self.width, Universe::clamp_dimension(height, self.width));
+    }
+
+    /// How many cells the cell at this position falls per tick at its current energy,
+    /// following the same rules as the update functions. Resting, immovable and empty
+    /// cells are 0.
+    pub fn velocity_at(&self, row: u32, column: u32) -> f32 {
+        if !(row < self.height && column < self.width) {
+            return 0.0;
+        }
+        let cell = self.cells[self.get_index(row, column)];
+        if cell.energy == 0 {
+            return 0.0;
+        }
+        let fall_distance = match cell.phase() {
+            Phase::Solid => cell.energy / 4 + 1,
+            Phase::Liquid => cell.energy.saturating_add(1),
+            _ => return 0.0,
+        };
+        fall_distance.min(self.max_fall_distance) as f32
+    }
+
+    /// Whether the cell at this position would stay put next tick, going by the gravity
+    /// direction for solids and liquids. Dead cells are never stable and immovable cells
+    /// always are.
+    pub fn is_stable(&self, row: u32, column: u32) -> bool {
+        if !(row < self.height && column < self.width) {
+            return false;
+        }
+        let (left, right) = (column.wrapping_sub(1), column + 1);
+        let cell = self.cells[self.get_index(row, column)];
+        let below = self.toward_gravity(row, column, 1, 0);
+        let below_sides = [self.toward_gravity(row, column, 1, -1), self.toward_gravity(row, column, 1, 1)];
+        let sides = [self.toward_gravity(row, column, 0, -1), self.toward_gravity(row, column, 0, 1)];
+        match cell.phase() {
+            Phase::Dead => false,
+            Phase::Immovable => true,
+            Phase::Solid => [below, below_sides[0], below_sides[1]]
+                .iter()
+                .all(|&pos| self.find_valid_positions_sinking(vec![pos], cell.density()).is_empty()),
+            Phase::Liquid if self.is_lighter_fluid(below.0, below.1, cell.density()).is_some() => false,
+            Phase::Liquid => [below, below_sides[0], below_sides[1], sides[0], sides[1]]
+                .iter()
+                .all(|&pos| self.find_valid_positions(vec![pos]).is_empty()),
+            Phase::Gas => [(row.wrapping_sub(1), column), (row, left), (row, right)]
+                .iter()
+                .all(|&pos| self.find_valid_positions(vec![pos]).is_empty()),
+        }
+    }
+
+    /// The cell ids of the (2 * radius + 1) square centered on a position, row by row
+    /// from the top left. Positions that fall outside the grid are 255. The radius is
+    /// clamped to the larger side of the grid, which already covers all of it.
+    pub fn neighborhood(&self, row: u32, column: u32, radius: u32) -> Vec<u8> {
+        let radius = radius.min(self.width.max(self.height));
+        let (row, column, radius) = (row as i64, column as i64, radius as i64);
+        let mut ids = Vec::with_capacity(((2 * radius + 1) * (2 * radius + 1)) as usize);
+        for r in row - radius..=row + radius {
+            for c in column - radius..=column + radius {
+                let inbound = r >= 0 && c >= 0 && r < self.height as i64 && c < self.width as i64;
+                if inbound {
+                    ids.push(self.cells[self.get_index(r as u32, c as u32)].id as u8);
+                } else {
+                    ids.push(OUT_OF_BOUNDS);
+                }
+            }
+        }
+        ids
+    }
+
+    /// Get a copy of a single cell along with its updated flag and temperature for
+    /// debugging, None if the position is out of bounds
+    pub fn inspect(&self, row: u32, column: u32) -> Option<Inspection> {
+        if !(row < self.height && column < self.width) {
+            return None;
+        }
+        let idx = self.get_index(row, column);
+        Some(Inspection {
+            cell: self.cells[idx],
+            was_updated: self.is_updated(&self.cells[idx]),
+            temperature: self.temperature[idx],
+        })
+    }
+
+    /// Turns every `from` cell into a fresh `to` cell, returns how many were replaced.
+    /// Falling cells come to rest, while fire and steam get their full lifetime.
+    pub fn replace_all(&mut self, from: CellType, to: CellType) -> u32 {
+        let mut replaced = 0;
+        for cell in self.cells.iter_mut().filter(|c| c.id == from) {
+            cell.set_cell(to);
+            replaced += 1;
+        }
+        replaced
+    }
+
+    /// Pinned cells are frozen in place and protected from being displaced or overwritten
+    /// by the simulation, a pinned empty cell stays empty
+    pub fn set_pinned(&mut self, row: u32, column: u32, pinned: bool) {
+        // The out of bounds check is done in javascript
+        let idx = self.get_index(row, column);
+        self.cells[idx].pinned = pinned;
+    }
+
+    /// Fill level of every cell, javascript uses it to shade partly filled water
+    pub fn fills(&self) -> Vec<u8> {
+        self.cells.iter().map(|c| c.fill).collect()
+    }
+
+    /// Every cell as four RGBA bytes, row by row, ready for `ImageData` and `putImageData`
+    /// at one pixel per cell. Empty cells are transparent.
+    pub fn render_to_rgba(&self) -> Vec<u8> {
+        self.cells.iter().flat_map(|c| c.id.color()).collect()
+    }
+
+    /// Energy of every cell, row by row, capped at `u16::MAX`. Together with the cell ids
+    /// this is the whole motion state, `load_energies` puts it back.
+    pub fn energies_snapshot(&self) -> Vec<u16> {
+        self.cells.iter().map(|c| c.energy.min(u16::MAX as u32) as u16).collect()
+    }
+
+    /// Overwrites the energy of every cell from a buffer laid out like `energies_snapshot`,
+    /// leaving the grid untouched when the length doesn't match it
+    pub fn load_energies(&mut self, energies: &[u16]) -> Result<(), String> {
+        if energies.len() != self.cells.len() {
+            return Err(format!("expected {} energies, found {}", self.cells.len(), energies.len()));
+        }
+        for (cell, &energy) in self.cells.iter_mut().zip(energies) {
+            cell.energy = energy as u32;
+        }
+        Ok(())
+    }
+
+    /// Activity of every cell, row by row. It is 255 where something moved during the
+    /// last tick and fades away afterwards, javascript can draw it as a heat map.
+    pub fn activity(&self) -> Vec<u8> {
+        self.cells.iter().map(|c| c.activity).collect()
+    }
+
+    /// Temperature at a position, positions outside the grid are at the ambient temperature
+    pub fn get_temperature(&self, row: u32, col: u32) -> f32 {
+        if !(row < self.height && col < self.width) {
+            return AMBIENT_TEMPERATURE;
+        }
+        self.temperature[self.get_index(row, col)]
+    }
+
+    /// Heats up a position by `amount` degrees, or cools it down when negative. The heat
+    /// spreads to the cells around it over the next ticks. The result is clamped to
+    /// between `MIN_TEMPERATURE` (absolute zero) and `MAX_TEMPERATURE`, and amounts that
+    /// aren't finite numbers are ignored.
+    pub fn add_heat(&mut self, row: u32, col: u32, amount: f32) {
+        if row < self.height && col < self.width && amount.is_finite() {
+            let idx = self.get_index(row, col);
+            self.temperature[idx] = (self.temperature[idx] + amount).clamp(MIN_TEMPERATURE, MAX_TEMPERATURE);
+        }
+    }
+
+    /// Humidity of every cell, row by row. Water is 255, solids near it are damp and
+    /// everything else is 0.
+    pub fn humidity(&self) -> Vec<u8> {
+        self.cells.iter().map(|c| c.humidity).collect()
+    }
+
+    /// Indices of the empty cells that can't be reached from the edge of the grid through
+    /// other empty cells, i.e. air pockets sealed inside structures
+    pub fn find_enclosed_regions(&self) -> Vec<u32> {
+        let mut reachable = vec![false; self.cells.len()];
+        let mut stack = Vec::new();
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let on_edge = row == 0 || col == 0 || row == self.height - 1 || col == self.width - 1;
+                if on_edge {
+                    stack.push((row, col));
+                }
+            }
+        }
+        while let Some((row, col)) = stack.pop() {
+            let idx = self.get_index(row, col);
+            if reachable[idx] || self.cells[idx].id != CellType::Dead {
+                continue;
+            }
+            reachable[idx] = true;
+            stack.extend(self.orthogonal_neighbors(row, col));
+        }
+
+        (0..self.cells.len())
+            .filter(|&idx| self.cells[idx].id == CellType::Dead && !reachable[idx])
+            .map(|idx| idx as u32)
+            .collect()
+    }
+
+    /// How many immovable cells aren't supported. A cell is supported when it is on the
+    /// bottom row or connects to it through other immovable cells, going up, down, left
+    /// and right. Powders and liquids underneath a structure don't hold it up.
+    pub fn count_floating(&self) -> u32 {
+        let is_structure = |idx: usize| self.cells[idx].phase() == Phase::Immovable;
+        let mut supported = vec![false; self.cells.len()];
+        let mut stack: Vec<(u32, u32)> = (0..self.width).map(|col| (self.height - 1, col)).collect();
+        while let Some((row, col)) = stack.pop() {
+            let idx = self.get_index(row, col);
+            if supported[idx] || !is_structure(idx) {
+                continue;
+            }
+            supported[idx] = true;
+            stack.extend(self.orthogonal_neighbors(row, col));
+        }
+
+        (0..self.cells.len()).filter(|&idx| is_structure(idx) && !supported[idx]).count() as u32
     }
 
-    pub fn set_width(&mut self, width: u32) {
-        self.width = width;
-        self.cells = (0..width * self.height)
-            .map(|_i| Cell::new(CellType::Dead))
-            .collect();
+    /// Whether empty cells connect two positions, see `shortest_path`
+    pub fn path_exists(&self, r0: u32, c0: u32, r1: u32, c1: u32) -> bool {
+        self.shortest_path(r0, c0, r1, c1).is_some()
     }
 
-    pub fn set_height(&mut self, height: u32) {
-        self.height = height;
-        self.cells = (0..self.width * height)
-            .map(|_i| Cell::new(CellType::Dead))
-            .collect();
+    /// Indices of the cells on a shortest path from the first position to the second that
+    /// only steps up, down, left and right through empty cells, both ends included. None
+    /// when there is no such path or either end isn't an empty cell inside the grid.
+    pub fn shortest_path(&self, r0: u32, c0: u32, r1: u32, c1: u32) -> Option<Vec<u32>> {
+        let is_open = |r: u32, c: u32| {
+            r < self.height && c < self.width && self.cells[self.get_index(r, c)].id == CellType::Dead
+        };
+        if !is_open(r0, c0) || !is_open(r1, c1) {
+            return None;
+        }
+        let (start, goal) = (self.get_index(r0, c0), self.get_index(r1, c1));
+        let mut came_from = vec![None; self.cells.len()];
+        came_from[start] = Some(start);
+        let mut queue = std::collections::VecDeque::from(vec![(r0, c0)]);
+        while let Some((row, col)) = queue.pop_front() {
+            let idx = self.get_index(row, col);
+            if idx == goal {
+                let mut path = vec![goal as u32];
+                let mut current = goal;
+                while current != start {
+                    current = came_from[current]?;
+                    path.push(current as u32);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for (r, c) in self.orthogonal_neighbors(row, col) {
+                let next = self.get_index(r, c);
+                if came_from[next].is_none() && is_open(r, c) {
+                    came_from[next] = Some(idx);
+                    queue.push_back((r, c));
+                }
+            }
+        }
+        None
+    }
+
+    /// Where cells of one type are, packed 8 cells to a byte. Cell `i` (counted row by
+    /// row) is bit `i % 8` of byte `i / 8`, with bit 0 the least significant one.
+    pub fn type_mask(&self, ct: CellType) -> Vec<u8> {
+        let mut mask = vec![0; self.cells.len().div_ceil(8)];
+        for (i, cell) in self.cells.iter().enumerate() {
+            if cell.id == ct {
+                mask[i / 8] |= 1 << (i % 8);
+            }
+        }
+        mask
+    }
+
+    /// Cell types of the grid shrunk (or stretched) to `out_width` by `out_height`, row by
+    /// row. Each output cell takes the type of the grid cell at its centre, for minimaps.
+    /// The output is capped at `MAX_CELLS` like the grid itself, by shortening its height.
+    pub fn thumbnail(&self, out_width: u32, out_height: u32) -> Vec<u8> {
+        let out_width = out_width.min(MAX_CELLS);
+        let out_height = out_height.min(MAX_CELLS / out_width.max(1));
+        let mut ids = Vec::with_capacity(out_width as usize * out_height as usize);
+        for out_row in 0..out_height {
+            let row = ((2 * out_row + 1) as u64 * self.height as u64 / (2 * out_height) as u64) as u32;
+            for out_col in 0..out_width {
+                let col = ((2 * out_col + 1) as u64 * self.width as u64 / (2 * out_width) as u64) as u32;
+                ids.push(self.cells[self.get_index(row, col)].id as u8);
+            }
+        }
+        ids
+    }
+
+    /// Type of the cell under a fractional grid position, `fx` across and `fy` down, with
+    /// cell (row, col) covering [col, col + 1) by [row, row + 1). Positions outside the grid
+    /// are clamped onto its edge, so renderers can upscale without doing the index math.
+    pub fn sample(&self, fx: f32, fy: f32) -> CellType {
+        let col = (fx.max(0.0) as u32).min(self.width - 1);
+        let row = (fy.max(0.0) as u32).min(self.height - 1);
+        self.cells[self.get_index(row, col)].id
+    }
+
+    /// How far the cell at each position moved during the last tick as a (columns, rows)
+    /// pair, row by row, so the buffer is twice as long as the grid. Rows count downwards
+    /// and distances are capped at the range of an `i8`.
+    pub fn motion_field(&self) -> Vec<i8> {
+        self.cells.iter().flat_map(|c| [c.motion.0, c.motion.1]).collect()
+    }
+
+    /// `FlowDirection` of every cell as a byte, row by row. Anything but water is `Still`.
+    pub fn flow_directions(&self) -> Vec<u8> {
+        self.cells.iter().map(|c| c.flow as u8).collect()
+    }
+
+    /// Run-length encoded cell types for sharing, e.g. `4x2 6. 2s` is a 4 by 2 grid whose
+    /// last two cells are sand. Runs go row by row from the top left.
+    pub fn to_rle(&self) -> String {
+        let mut rle = format!("{}x{}", self.width, self.height);
+        let mut push_run = |run: u32, ct: CellType| rle.push_str(&format!(" {}{}", run, ct.symbol()));
+        // Runs of empty cells are the gaps between occupied ones
+        let (mut run, mut next): (Option<(CellType, u32)>, u32) = (None, 0);
+        for (idx, cell) in self.occupied_cells() {
+            if idx > next {
+                if let Some((ct, length)) = run.take() {
+                    push_run(length, ct);
+                }
+                push_run(idx - next, CellType::Dead);
+            }
+            run = match run {
+                Some((ct, length)) if ct == cell.id => Some((ct, length + 1)),
+                Some((ct, length)) => {
+                    push_run(length, ct);
+                    Some((cell.id, 1))
+                }
+                None => Some((cell.id, 1)),
+            };
+            next = idx + 1;
+        }
+        if let Some((ct, length)) = run {
+            push_run(length, ct);
+        }
+        if next < self.cells.len() as u32 {
+            push_run(self.cells.len() as u32 - next, CellType::Dead);
+        }
+        rle
+    }
+
+    /// Rebuilds a universe from the output of `to_rle`
+    pub fn from_rle(rle: &str) -> Result<Universe, String> {
+        let mut tokens = rle.split_whitespace();
+        let size = tokens.next().ok_or("missing grid size")?;
+        let (width, height) = size
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+            .filter(|&(w, h)| w > 0 && h > 0)
+            .ok_or_else(|| format!("invalid grid size `{}`", size))?;
+
+        let expected = width
+            .checked_mul(height)
+            .filter(|&area| area <= MAX_CELLS)
+            .ok_or_else(|| format!("grid size `{}` is too large", size))? as usize;
+        let mut cells = Vec::new();
+        for token in tokens {
+            let symbol = token.chars().last().unwrap();
+            let run = &token[..token.len() - symbol.len_utf8()];
+            let ct = CellType::from_symbol(symbol)
+                .ok_or_else(|| format!("unknown element `{}`", symbol))?;
+            let run: usize = run.parse().map_err(|_| format!("invalid run `{}`", token))?;
+            if run > expected - cells.len() {
+                return Err(format!("more than the expected {} cells", expected));
+            }
+            cells.resize(cells.len() + run, Cell::new(ct));
+        }
+        if cells.len() != expected {
+            return Err(format!("expected {} cells, found {}", expected, cells.len()));
+        }
+
+        let mut universe = Universe::new_with_size(width, height);
+        universe.cells = cells;
+        Ok(universe)
+    }
+
+    /// The grid as bytes for saving, e.g. to `localStorage`: width and height as
+    /// little endian u32s and the update stamp, then every cell row by row with its type,
+    /// energy as a little endian u32, fill, pin, flow, activity, humidity, motion and
+    /// update stamp. Settings and layers other than the active one aren't included.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(SAVE_HEADER_BYTES + self.cells.len() * SAVE_CELL_BYTES);
+        data.extend_from_slice(&self.width.to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes());
+        data.push(self.update_stamp);
+        for cell in &self.cells {
+            data.push(cell.id as u8);
+            data.extend_from_slice(&cell.energy.to_le_bytes());
+            data.extend_from_slice(&[
+                cell.fill,
+                cell.pinned as u8,
+                cell.flow as u8,
+                cell.activity,
+                cell.humidity,
+                cell.motion.0 as u8,
+                cell.motion.1 as u8,
+                cell.update_stamp,
+            ]);
+        }
+        data
+    }
+
+    /// Rebuilds a universe from the output of `serialize`, None when the buffer is
+    /// truncated, its length doesn't match the size it declares or a byte is invalid
+    pub fn deserialize(data: &[u8]) -> Option<Universe> {
+        let header = data.get(..SAVE_HEADER_BYTES)?;
+        let width = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let height = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let area = width.checked_mul(height).filter(|&area| area > 0 && area <= MAX_CELLS)?;
+        let body = &data[SAVE_HEADER_BYTES..];
+        if body.len() != area as usize * SAVE_CELL_BYTES || header[8] == 0 {
+            return None;
+        }
+
+        let mut cells = Vec::with_capacity(area as usize);
+        for bytes in body.chunks_exact(SAVE_CELL_BYTES) {
+            let id = all_elements().into_iter().find(|&ct| ct as u8 == bytes[0])?;
+            cells.push(Cell {
+                energy: u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]),
+                fill: bytes[5],
+                pinned: bytes[6] != 0,
+                flow: FlowDirection::from_u8(bytes[7])?,
+                activity: bytes[8],
+                humidity: bytes[9],
+                motion: (bytes[10] as i8, bytes[11] as i8),
+                update_stamp: bytes[12],
+                ..Cell::new(id)
+            });
+        }
+        let mut universe = Universe::new_with_size(width, height);
+        universe.cells = cells;
+        universe.update_stamp = header[8];
+        Some(universe)
+    }
+
+    /// Paints a brush shape centered on a position, cells outside the grid are skipped
+    pub fn stamp(&mut self, row: u32, column: u32, brush: Brush, ct: CellType) {
+        for (dr, dc) in brush.offsets(&self.rng) {
+            let (r, c) = (row as i64 + dr, column as i64 + dc);
+            if r >= 0 && c >= 0 && r < self.height as i64 && c < self.width as i64 {
+                self.paint(r as u32, c as u32, ct);
+            }
+        }
+    }
+
+    /// Paints the `w` by `h` rectangle with its top left corner at a position, the part
+    /// outside the grid is cut off
+    pub fn fill_rect(&mut self, row: u32, col: u32, w: u32, h: u32, ct: CellType) {
+        let row_end = (row as u64 + h as u64).min(self.height as u64) as u32;
+        let col_end = (col as u64 + w as u64).min(self.width as u64) as u32;
+        for r in row..row_end {
+            for c in col..col_end {
+                self.paint(r, c, ct);
+            }
+        }
+    }
+
+    /// Paints a straight line of cells between two positions, both ends included. The part
+    /// outside the grid is cut off.
+    pub fn draw_line(&mut self, row0: u32, col0: u32, row1: u32, col1: u32, ct: CellType) {
+        let (mut row, mut col) = (row0 as i64, col0 as i64);
+        let (end_row, end_col) = (row1 as i64, col1 as i64);
+        // Bresenham's line algorithm, stepping along both axes by the same rules keeps a
+        // line and its mirror image cell for cell symmetric
+        let (d_row, d_col) = ((end_row - row).abs(), -(end_col - col).abs());
+        let (step_row, step_col) = ((end_row - row).signum(), (end_col - col).signum());
+        let mut error = d_row + d_col;
+        loop {
+            if row < self.height as i64 && col < self.width as i64 {
+                self.paint(row as u32, col as u32, ct);
+            }
+            if (row, col) == (end_row, end_col) {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= d_col {
+                error += d_col;
+                row += step_row;
+            }
+            if doubled <= d_row {
+                error += d_row;
+                col += step_col;
+            }
+        }
+    }
+
+    /// Empties the whole grid, unpinning every cell and cooling it back down, without
+    /// reallocating it. The generation counter starts over from 0.
+    pub fn clear(&mut self) {
+        self.cells.fill(Cell::new(CellType::Dead));
+        self.temperature.fill(AMBIENT_TEMPERATURE);
+        self.swap_streaks.clear();
+        self.held.clear();
+        self.generation = 0;
+        self.tick_parity = false;
+    }
+
+    /// Paints every cell within `radius` of a center, cells outside the grid are skipped.
+    /// One call paints a whole brush dab instead of one `set_cell` per cell.
+    pub fn paint_circle(&mut self, center_row: u32, center_col: u32, radius: u32, ct: CellType) {
+        let (row, col, radius) = (center_row as i64, center_col as i64, radius as i64);
+        for r in (row - radius).max(0)..=(row + radius).min(self.height as i64 - 1) {
+            for c in (col - radius).max(0)..=(col + radius).min(self.width as i64 - 1) {
+                if (r - row) * (r - row) + (c - col) * (c - col) <= radius * radius {
+                    self.paint(r as u32, c as u32, ct);
+                }
+            }
+        }
+    }
+
+    /// Copies every cell of `other` onto this grid with its top left corner at the given
+    /// position, keeping energy, fill, pins and temperature. Parts that don't fit are cut
+    /// off, and with `transparent_dead` the empty cells of `other` leave what is underneath
+    /// alone.
+    pub fn blit(&mut self, other: &Universe, dest_row: u32, dest_col: u32, transparent_dead: bool) {
+        for row in 0..other.height {
+            for col in 0..other.width {
+                let (r, c) = (dest_row as u64 + row as u64, dest_col as u64 + col as u64);
+                if r >= self.height as u64 || c >= self.width as u64 {
+                    continue;
+                }
+                let cell = other.cells[other.get_index(row, col)];
+                if transparent_dead && cell.id == CellType::Dead {
+                    continue;
+                }
+                let idx = self.get_index(r as u32, c as u32);
+                self.cells[idx] = cell;
+                // The stamp belongs to `other`'s passes, it could pass for one of ours
+                self.cells[idx].update_stamp = 0;
+                self.temperature[idx] = other.temperature[other.get_index(row, col)];
+                #[cfg(feature = "particle-ids")]
+                {
+                    self.cells[idx].particle = 0; // A copy is a new particle
+                }
+            }
+        }
+    }
+
+    /// Fills a band `thickness` cells wide around the edge of the grid with `ct`, which
+    /// should be immovable (usually rock) to keep everything inside
+    pub fn seal_borders(&mut self, ct: CellType, thickness: u32) {
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let edge_distance = row.min(col).min(self.height - 1 - row).min(self.width - 1 - col);
+                if edge_distance < thickness {
+                    self.paint(row, col, ct);
+                }
+            }
+        }
+    }
+
+    /// Writes text in a 3 by 5 cell font with its top left corner at a position. Cells
+    /// outside the grid and characters the font doesn't have are skipped.
+    pub fn stamp_text(&mut self, row: u32, column: u32, text: &str, ct: CellType) {
+        let glyphs = text.chars().filter_map(font::glyph);
+        for (i, rows) in glyphs.enumerate() {
+            let left = column as u64 + i as u64 * (font::GLYPH_WIDTH as u64 + 1);
+            for (dr, bits) in rows.iter().enumerate() {
+                for dc in 0..font::GLYPH_WIDTH {
+                    let (r, c) = (row as u64 + dr as u64, left + dc as u64);
+                    let is_set = bits & (1 << (font::GLYPH_WIDTH - 1 - dc)) != 0;
+                    if is_set && r < self.height as u64 && c < self.width as u64 {
+                        self.paint(r as u32, c as u32, ct);
+                    }
+                }
+            }
+        }
     }
 
     pub fn set_cell(&mut self, row: u32, column: u32, ct: CellType) {
         // The out of bounds check is done in javascript
-        let idx = self.get_index(row, column);
-        self.cells[idx].set_cell(ct);
+        self.paint(row, column, ct);
+    }
+
+    /// Type of the cell at a position, positions outside the grid read as `Dead`
+    pub fn get_cell(&self, row: u32, column: u32) -> CellType {
+        if !(row < self.height && column < self.width) {
+            return CellType::Dead;
+        }
+        self.cells[self.get_index(row, column)].id
+    }
+
+    /// How many cells of the given type there are, e.g. for a particle counter
+    pub fn count_cells(&self, ct: CellType) -> u32 {
+        self.cells.iter().filter(|c| c.id == ct).count() as u32
+    }
+
+    /// With density aware painting, solids and liquids painted into a lighter liquid or
+    /// gas sink straight to the bottom of it instead of starting where they were painted
+    pub fn set_density_aware_paint(&mut self, enabled: bool) {
+        self.density_aware_paint = enabled;
     }
 }
 
@@ -303,16 +3747,758 @@ impl fmt::Display for Universe {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for line in self.cells.as_slice().chunks(self.width as usize) {
             for &cell in line {
-                let symbol = if cell.id == CellType::Dead {
-                    '◻'
-                } else {
-                    '◼'
+                let symbol = match cell.id {
+                    CellType::Dead => '◻',
+                    CellType::Oil => '◍', // Oil and water would look the same otherwise
+                    _ => '◼',
                 };
                 write!(f, "{}", symbol)?;
             }
-            write!(f, "\n")?;
+            writeln!(f)?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_natively() {
+        let mut universe = Universe::new_seeded(8, 8, 1);
+        universe.set_cell(0, 3, CellType::Sand);
+        universe.tick_n(20);
+        assert_eq!(universe.get_cell(7, 3), CellType::Sand);
+        assert_eq!(universe.count_cells(CellType::Sand), 1);
+        assert_eq!(universe.generation(), 20);
+    }
+
+    #[test]
+    fn steady_stream_is_not_held() {
+        let run = |limit| {
+            let mut universe = Universe::new_seeded(1, 40, 7);
+            universe.set_oscillation_limit(limit);
+            universe.set_rain(1.0, CellType::Sand);
+            universe.tick_n(30);
+            universe.cell_ids().collect::<Vec<_>>()
+        };
+        assert_eq!(run(2), run(0));
+    }
+
+    #[test]
+    fn replaced_fire_burns_out() {
+        let mut universe = Universe::new_seeded(5, 5, 3);
+        universe.set_cell(2, 2, CellType::Sand);
+        assert_eq!(universe.replace_all(CellType::Sand, CellType::Fire), 1);
+        assert_eq!(universe.inspect(2, 2).unwrap().cell.energy(), FIRE_LIFETIME);
+        universe.tick_n(FIRE_LIFETIME);
+        assert_eq!(universe.count_cells(CellType::Fire), 0);
+    }
+
+    #[test]
+    fn fire_spreads_through_flammable_cells() {
+        let mut universe = Universe::new_seeded(6, 3, 5);
+        universe.fill_rect(0, 0, 6, 3, CellType::Rock);
+        universe.fill_rect(1, 1, 4, 1, CellType::Vine);
+        universe.set_cell(1, 0, CellType::Fire);
+        universe.tick_n(100);
+        assert_eq!(universe.count_cells(CellType::Vine), 0);
+        assert_eq!(universe.count_cells(CellType::Rock), 13);
+    }
+
+    #[test]
+    fn fire_boils_touching_water() {
+        let mut universe = Universe::new_seeded(8, 4, 11);
+        universe.fill_rect(3, 0, 8, 1, CellType::Water);
+        universe.fill_rect(2, 0, 8, 1, CellType::Fire);
+        universe.tick();
+        assert!(universe.count_cells(CellType::Steam) > 0);
+    }
+
+    #[test]
+    fn heat_spreads_and_fades() {
+        let mut universe = Universe::new_seeded(5, 5, 2);
+        universe.fill_rect(0, 0, 5, 5, CellType::Rock);
+        universe.add_heat(2, 2, 500.0);
+        universe.tick_n(5);
+        let warmed = universe.get_temperature(2, 3);
+        assert!(warmed > AMBIENT_TEMPERATURE + 10.0);
+        universe.tick_n(500);
+        assert!(universe.get_temperature(2, 3) < warmed);
+        assert!((universe.get_temperature(2, 2) - AMBIENT_TEMPERATURE).abs() < 1.0);
+    }
+
+    #[test]
+    fn heated_water_boils() {
+        let mut universe = Universe::new_seeded(3, 3, 4);
+        universe.fill_rect(0, 0, 3, 3, CellType::Rock);
+        universe.set_cell(1, 1, CellType::Water);
+        for _ in 0..10 {
+            universe.add_heat(1, 1, 500.0);
+            universe.tick();
+        }
+        assert_eq!(universe.get_cell(1, 1), CellType::Steam);
+    }
+
+    #[test]
+    fn add_heat_is_clamped() {
+        let mut universe = Universe::new_seeded(3, 3, 4);
+        universe.add_heat(1, 1, f32::NAN);
+        universe.add_heat(1, 1, f32::INFINITY);
+        assert_eq!(universe.get_temperature(1, 1), AMBIENT_TEMPERATURE);
+        universe.add_heat(1, 1, 1e30);
+        universe.add_heat(0, 0, -1e30);
+        assert_eq!(universe.get_temperature(1, 1), MAX_TEMPERATURE);
+        assert_eq!(universe.get_temperature(0, 0), MIN_TEMPERATURE);
+        universe.tick_n(10);
+        assert!(universe.temperature.iter().all(|t| t.is_finite()));
+    }
+
+    #[test]
+    fn heat_crosses_wrapped_edges() {
+        let run = |wrap| {
+            let mut universe = Universe::new_seeded(9, 3, 6);
+            universe.fill_rect(0, 0, 9, 3, CellType::Rock);
+            universe.set_wrap(wrap);
+            universe.add_heat(1, 0, 500.0);
+            universe.tick_n(2);
+            universe.get_temperature(1, 8)
+        };
+        assert!(run(true) > AMBIENT_TEMPERATURE + 10.0);
+        assert_eq!(run(false), AMBIENT_TEMPERATURE);
+    }
+
+    #[test]
+    fn helpers_follow_sideways_gravity() {
+        let mut universe = Universe::new_seeded(8, 4, 8);
+        universe.set_gravity(GravityDir::Right);
+        universe.set_cell(1, 0, CellType::Sand);
+        assert!(!universe.is_stable(1, 0));
+        universe.settle_powders(50);
+        assert_eq!(universe.get_cell(1, 7), CellType::Sand);
+        assert!(universe.is_stable(1, 7));
+
+        universe.set_density_aware_paint(true);
+        universe.fill_rect(2, 3, 5, 1, CellType::Water);
+        universe.set_cell(2, 3, CellType::Sand);
+        assert_eq!(universe.get_cell(2, 7), CellType::Sand);
+    }
+
+    #[test]
+    fn settled_tar_reaches_the_floor() {
+        let mut universe = Universe::new_seeded(3, 6, 10);
+        universe.fill_rect(0, 0, 3, 1, CellType::Tar);
+        let passes = universe.settle_liquids(10_000);
+        assert!(passes < 10_000);
+        assert!((0..3).all(|col| universe.get_cell(5, col) == CellType::Tar));
+    }
+
+    #[test]
+    fn hourglass_matches_golden_file() {
+        let mut universe = Universe::hourglass();
+        assert_eq!(universe.to_rle(), include_str!("../testdata/hourglass.rle").trim_end());
+        let (width, height) = (universe.width(), universe.height());
+        for row in [height / 2 - 1, height / 2] {
+            let neck: Vec<_> = (width / 2 - 2..=width / 2 + 1).map(|col| universe.get_cell(row, col)).collect();
+            assert_eq!(neck, [CellType::Rock, CellType::Dead, CellType::Dead, CellType::Rock]);
+        }
+        let sand = universe.count_cells(CellType::Sand);
+        universe.tick_n(300);
+        assert_eq!(universe.count_cells(CellType::Sand), sand);
+        // Nothing gets out into the corners outside the walls
+        for row in 0..height {
+            for col in (1..width - 1).filter(|&col| col.min(width - 1 - col) + 3 < row.min(height - 1 - row)) {
+                assert_ne!(universe.get_cell(row, col), CellType::Sand, "sand outside at ({}, {})", row, col);
+            }
+        }
+        let fallen = (universe.height() / 2..universe.height())
+            .flat_map(|row| (0..universe.width()).map(move |col| (row, col)))
+            .filter(|&(row, col)| universe.get_cell(row, col) == CellType::Sand)
+            .count();
+        assert!(fallen > 0);
+    }
+
+    #[test]
+    fn displaced_water_moves_once_per_tick() {
+        let mut universe = Universe::new_seeded(3, 4, 12);
+        universe.set_surface_tension(0.0);
+        universe.fill_rect(2, 0, 3, 2, CellType::Rock);
+        universe.set_cell(2, 1, CellType::Water);
+        universe.set_cell(1, 1, CellType::Sand);
+        universe.tick();
+        // The water already had its turn when the sand sank into it, so it stays put
+        assert_eq!(universe.get_cell(1, 1), CellType::Water);
+        assert_eq!(universe.get_cell(2, 1), CellType::Sand);
+        // On the next tick it has its turn again and spreads out over the sand
+        universe.tick();
+        assert!(universe.get_cell(1, 0) == CellType::Water || universe.get_cell(1, 2) == CellType::Water);
+    }
+
+    #[test]
+    fn ticks_conserve_water_fill() {
+        let total_fill = |universe: &Universe| -> u32 {
+            universe.get_cells().iter().filter(|c| c.id == CellType::Water).map(|c| c.fill as u32).sum()
+        };
+        let mut universe = Universe::new_seeded(16, 8, 13);
+        universe.fill_rect(0, 0, 4, 4, CellType::Water);
+        let fill = total_fill(&universe);
+        for _ in 0..50 {
+            universe.tick();
+            assert_eq!(total_fill(&universe), fill);
+        }
+    }
+
+    #[test]
+    fn sliding_grain_keeps_falling() {
+        let mut universe = Universe::new_seeded(3, 12, 14);
+        universe.set_cell(5, 1, CellType::Rock);
+        universe.set_cell(0, 1, CellType::Sand);
+        universe.tick_n(5);
+        // Slid off the rock diagonally and kept the momentum from the fall onto it
+        let col = if universe.get_cell(5, 0) == CellType::Sand { 0 } else { 2 };
+        assert_eq!(universe.get_cell(5, col), CellType::Sand);
+        assert_eq!(universe.inspect(5, col).unwrap().cell.energy(), 4);
+        universe.tick();
+        assert_eq!(universe.get_cell(7, col), CellType::Sand);
+        universe.tick_n(10);
+        assert_eq!(universe.get_cell(11, col), CellType::Sand);
+        assert_eq!(universe.inspect(11, col).unwrap().cell.energy(), 0);
+    }
+
+    #[test]
+    fn long_fall_reaches_terminal_velocity() {
+        let mut universe = Universe::new_seeded(1, 400, 15);
+        universe.set_terminal_velocity(8);
+        universe.set_cell(0, 0, CellType::Sand);
+        let find = |universe: &Universe| (0..400).find(|&row| universe.get_cell(row, 0) == CellType::Sand).unwrap();
+        let mut row = find(&universe);
+        let mut reaches = Vec::new();
+        for _ in 0..60 {
+            universe.tick();
+            let next = find(&universe);
+            reaches.push(next - row);
+            row = next;
+        }
+        assert!(reaches.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(reaches[30..].iter().all(|&reach| reach == 3));
+    }
+
+    #[test]
+    fn degenerate_dimensions_are_clamped() {
+        let mut universe = Universe::new_seeded(0, 0, 16);
+        assert_eq!((universe.width(), universe.height()), (1, 1));
+        universe.tick();
+        universe.set_width(0);
+        universe.set_height(0);
+        assert_eq!((universe.width(), universe.height()), (1, 1));
+        assert_eq!(Universe::clamp_dimension(u32::MAX, u32::MAX), 1);
+        assert_eq!(Universe::clamp_dimension(u32::MAX, 1), MAX_CELLS);
+        assert_eq!(Universe::clamp_dimension(0, 5), 1);
+        assert!(Universe::from_rle("4294967295x2 1s").is_err());
+        let mut header = u32::MAX.to_le_bytes().to_vec();
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        header.push(1);
+        assert!(Universe::deserialize(&header).is_none());
+    }
+
+    #[test]
+    fn chunked_scan_matches_full_scan() {
+        for gravity in [GravityDir::Down, GravityDir::Up, GravityDir::Left, GravityDir::Right] {
+            for mode in [ScanMode::Ordered, ScanMode::Shuffled] {
+                let run = |chunked| {
+                    let mut universe = Universe::new_seeded(48, 40, 17);
+                    universe.set_chunked(chunked);
+                    universe.set_gravity(gravity);
+                    universe.set_scan_mode(mode);
+                    universe.fill_rect(20, 10, 30, 4, CellType::Rock);
+                    universe.fill_rect(2, 3, 10, 6, CellType::Sand);
+                    universe.fill_rect(30, 35, 10, 5, CellType::Water);
+                    universe.tick_n(80);
+                    (universe.hash_state(), universe.cell_ids().collect::<Vec<_>>())
+                };
+                assert_eq!(run(true), run(false));
+            }
+        }
+    }
+
+    #[test]
+    fn update_stamps_survive_wrapping_around() {
+        let mut universe = Universe::new_seeded(1, 600, 18);
+        universe.set_max_fall_distance(1);
+        universe.set_cell(0, 0, CellType::Sand);
+        for tick in 1..=500 {
+            universe.tick();
+            // One cell further every tick, through the stamps wrapping past 255 and back
+            assert_eq!(universe.get_cell(tick, 0), CellType::Sand);
+            let cells = unsafe { std::slice::from_raw_parts(universe.cells(), 600) };
+            assert_eq!(cells[tick as usize], CellType::Sand);
+        }
+    }
+
+    #[test]
+    fn sand_sinks_below_water() {
+        let mut universe = Universe::new_seeded(1, 8, 19);
+        universe.fill_rect(2, 0, 1, 6, CellType::Water);
+        universe.set_cell(0, 0, CellType::Sand);
+        universe.tick_n(30);
+        assert_eq!(universe.get_cell(7, 0), CellType::Sand);
+        assert_eq!(universe.count_cells(CellType::Water), 6);
+    }
+
+    #[test]
+    fn oil_floats_on_water() {
+        let mut universe = Universe::new_seeded(1, 6, 20);
+        universe.fill_rect(2, 0, 1, 2, CellType::Water);
+        universe.fill_rect(4, 0, 1, 2, CellType::Oil);
+        universe.tick_n(30);
+        let column: Vec<_> = (0..6).map(|row| universe.get_cell(row, 0)).collect();
+        assert_eq!(column[2..], [CellType::Oil, CellType::Oil, CellType::Water, CellType::Water]);
+        assert!(universe.to_string().contains('◍'));
+    }
+
+    #[test]
+    fn burning_oil_line_burns_out() {
+        let mut universe = Universe::new_seeded(3, 3, 21);
+        universe.fill_rect(2, 0, 3, 1, CellType::Rock);
+        universe.fill_rect(1, 0, 3, 1, CellType::Oil);
+        universe.set_cell(0, 1, CellType::Fire);
+        universe.tick_n(100);
+        assert!((0..2).all(|row| (0..3).all(|col| universe.get_cell(row, col) == CellType::Dead)));
+        assert_eq!(universe.count_cells(CellType::Rock), 3);
+    }
+
+    #[test]
+    fn water_next_to_fire_turns_to_steam() {
+        let mut universe = Universe::new_seeded(3, 2, 22);
+        universe.fill_rect(0, 0, 3, 1, CellType::Rock);
+        universe.set_cell(1, 2, CellType::Rock);
+        universe.set_cell(1, 0, CellType::Fire);
+        universe.set_cell(1, 1, CellType::Water);
+        universe.tick_n(10);
+        assert_eq!(universe.get_cell(1, 1), CellType::Steam);
+    }
+
+    #[test]
+    fn steam_stays_in_the_top_row() {
+        let mut universe = Universe::new_seeded(1, 3, 23);
+        universe.set_cell(0, 0, CellType::Steam);
+        universe.tick();
+        assert_eq!(universe.get_cell(0, 0), CellType::Steam);
+        universe.tick_n(STEAM_LIFETIME);
+        assert_eq!(universe.count_cells(CellType::Steam), 0);
+    }
+
+    #[test]
+    fn acid_eats_rock() {
+        let mut universe = Universe::new_seeded(1, 4, 24);
+        universe.set_cell(3, 0, CellType::Rock);
+        universe.set_cell(0, 0, CellType::Acid);
+        universe.tick_n(200);
+        assert_eq!(universe.count_cells(CellType::Rock), 0);
+        assert_eq!(universe.count_cells(CellType::Acid), 0);
+    }
+
+    #[test]
+    fn same_seed_plays_out_the_same() {
+        let run = |seed| {
+            let mut universe = Universe::new_seeded(32, 32, seed);
+            universe.set_scan_mode(ScanMode::Shuffled);
+            universe.fill_rect(0, 4, 10, 10, CellType::Sand);
+            universe.fill_rect(0, 18, 10, 10, CellType::Water);
+            universe.tick_n(100);
+            universe.serialize()
+        };
+        assert_eq!(run(25), run(25));
+        assert_ne!(run(25), run(26));
+    }
+
+    #[test]
+    fn rgba_buffer_follows_resizes() {
+        let mut universe = Universe::new_seeded(4, 4, 27);
+        universe.set_cell(0, 0, CellType::Sand);
+        assert_eq!(universe.render_to_rgba().len(), 4 * 4 * 4);
+        universe.set_width(10);
+        universe.set_height(7);
+        let rgba = universe.render_to_rgba();
+        assert_eq!(rgba.len(), 10 * 7 * 4);
+        assert_eq!(rgba[..4], CellType::Sand.color());
+        assert_eq!(rgba[4..8], CellType::Dead.color());
+    }
+
+    #[test]
+    fn reads_single_cells_and_counts() {
+        let mut universe = Universe::new_seeded(5, 5, 28);
+        universe.fill_rect(4, 0, 5, 1, CellType::Rock);
+        universe.set_cell(0, 0, CellType::Sand);
+        universe.set_cell(0, 4, CellType::Sand);
+        assert_eq!(universe.get_cell(0, 4), CellType::Sand);
+        assert_eq!(universe.get_cell(5, 0), CellType::Dead);
+        assert_eq!(universe.get_cell(0, u32::MAX), CellType::Dead);
+        assert_eq!(universe.count_cells(CellType::Rock), 5);
+        assert_eq!(universe.count_cells(CellType::Sand), 2);
+        assert_eq!(universe.count_cells(CellType::Dead), 18);
+    }
+
+    #[test]
+    fn fill_rect_then_clear() {
+        let mut universe = Universe::new_seeded(6, 6, 29);
+        universe.fill_rect(1, 2, 3, 2, CellType::Rock);
+        for row in 0..6 {
+            for col in 0..6 {
+                let inside = (1..3).contains(&row) && (2..5).contains(&col);
+                assert_eq!(universe.get_cell(row, col) == CellType::Rock, inside);
+            }
+        }
+        universe.fill_rect(4, 4, 100, 100, CellType::Rock);
+        assert_eq!(universe.count_cells(CellType::Rock), 6 + 4);
+        universe.set_pinned(0, 0, true);
+        universe.tick_n(3);
+        universe.clear();
+        assert_eq!(universe.count_cells(CellType::Dead), 36);
+        assert!(!universe.inspect(0, 0).unwrap().cell.pinned());
+        assert_eq!(universe.generation(), 0);
+    }
+
+    #[test]
+    fn paints_a_disc_clipped_at_corners() {
+        let mut universe = Universe::new_seeded(20, 20, 30);
+        universe.paint_circle(10, 10, 3, CellType::Rock);
+        assert_eq!(universe.count_cells(CellType::Rock), 29);
+        universe.clear();
+        universe.paint_circle(0, 0, 3, CellType::Rock);
+        universe.paint_circle(19, 19, 3, CellType::Rock);
+        assert_eq!(universe.count_cells(CellType::Rock), 2 * 11);
+        universe.paint_circle(u32::MAX, u32::MAX, 3, CellType::Sand);
+        assert_eq!(universe.count_cells(CellType::Sand), 0);
+    }
+
+    #[test]
+    fn serialize_round_trips() {
+        let mut universe = Universe::new_seeded(12, 10, 31);
+        universe.fill_rect(0, 0, 6, 3, CellType::Sand);
+        universe.fill_rect(0, 6, 6, 3, CellType::Water);
+        universe.set_pinned(9, 0, true);
+        universe.tick_n(4);
+        let data = universe.serialize();
+        let restored = Universe::deserialize(&data).unwrap();
+        // Particle ids aren't saved, every other field of every cell is
+        assert!(restored.cell_ids().eq(universe.cell_ids()));
+        assert_eq!(restored.serialize(), data);
+        assert!(Universe::deserialize(&data[..data.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn wrapped_sand_comes_back_in_at_the_top() {
+        let mut universe = Universe::new_seeded(1, 5, 32);
+        universe.set_wrap(true);
+        universe.set_cell(4, 0, CellType::Sand);
+        universe.tick();
+        assert_eq!(universe.get_cell(0, 0), CellType::Sand);
+        assert_eq!(universe.count_cells(CellType::Sand), 1);
+        universe.set_wrap(false);
+        universe.tick_n(10);
+        assert_eq!(universe.get_cell(4, 0), CellType::Sand);
+    }
+
+    #[test]
+    fn sideways_gravity_piles_sand_on_the_wall() {
+        let mut universe = Universe::new_seeded(10, 6, 33);
+        universe.set_gravity(GravityDir::Right);
+        universe.fill_rect(1, 0, 2, 4, CellType::Sand);
+        universe.tick_n(60);
+        let against_wall = (0..6).filter(|&row| universe.get_cell(row, 9) == CellType::Sand).count();
+        assert!(against_wall >= 4);
+        assert!((0..6).all(|row| (0..7).all(|col| universe.get_cell(row, col) == CellType::Dead)));
+    }
+
+    #[test]
+    fn tick_n_matches_single_ticks() {
+        let start = |seed| {
+            let mut universe = Universe::new_seeded(24, 24, seed);
+            universe.set_scan_mode(ScanMode::Shuffled);
+            universe.fill_rect(0, 0, 12, 8, CellType::Sand);
+            universe.fill_rect(0, 12, 12, 8, CellType::Water);
+            universe
+        };
+        let (mut batched, mut single) = (start(34), start(34));
+        batched.tick_n(5);
+        for _ in 0..5 {
+            single.tick();
+        }
+        assert_eq!(batched.serialize(), single.serialize());
+        assert_eq!(batched.generation(), 5);
+        assert_eq!(batched.hash_state(), single.hash_state());
+    }
+
+    #[test]
+    fn skipping_quiet_lines_matches_full_scan() {
+        let run = |chunked| {
+            let mut universe = Universe::new_seeded(40, 120, 35);
+            universe.set_chunked(chunked);
+            universe.fill_rect(0, 10, 20, 4, CellType::Sand);
+            universe.fill_rect(60, 0, 40, 1, CellType::Rock);
+            universe.fill_rect(110, 25, 10, 5, CellType::Water);
+            let mut frames = Vec::new();
+            for _ in 0..10 {
+                universe.tick_n(20);
+                frames.push(universe.hash_state());
+            }
+            universe.set_rain(2.0, CellType::Sand);
+            universe.tick_n(60);
+            frames.push(universe.hash_state());
+            frames
+        };
+        assert_eq!(run(true), run(false));
+    }
+
+    #[test]
+    fn falling_energy_grows_then_resets() {
+        let mut universe = Universe::new_seeded(1, 40, 36);
+        universe.set_cell(0, 0, CellType::Sand);
+        let energy = |universe: &mut Universe| {
+            let energies = unsafe { std::slice::from_raw_parts(universe.energies(), 40) };
+            energies.iter().copied().max().unwrap()
+        };
+        let mut last = energy(&mut universe);
+        for _ in 0..5 {
+            universe.tick();
+            let next = energy(&mut universe);
+            assert!(next > last);
+            last = next;
+        }
+        universe.tick_n(20);
+        assert_eq!(universe.get_cell(39, 0), CellType::Sand);
+        assert_eq!(energy(&mut universe), 0);
+    }
+
+    #[test]
+    fn sand_column_piles_up_symmetrically() {
+        let mut universe = Universe::new_seeded(61, 40, 37);
+        universe.fill_rect(0, 30, 1, 30, CellType::Sand);
+        universe.tick_n(200);
+        let count = |cols: std::ops::Range<u32>| {
+            cols.flat_map(|col| (0..40).map(move |row| (row, col)))
+                .filter(|&(row, col)| universe.get_cell(row, col) == CellType::Sand)
+                .count() as i64
+        };
+        let (left, right) = (count(0..30), count(31..61));
+        assert!(left > 5 && right > 5);
+        assert!((left - right).abs() <= 4, "{} left of the column, {} right of it", left, right);
+    }
+
+    #[test]
+    fn water_levels_even_out_in_a_u_tube() {
+        let mut universe = Universe::new_seeded(21, 40, 38);
+        universe.fill_rect(39, 0, 21, 1, CellType::Rock);
+        universe.fill_rect(0, 0, 1, 39, CellType::Rock);
+        universe.fill_rect(0, 20, 1, 39, CellType::Rock);
+        universe.fill_rect(0, 10, 1, 35, CellType::Rock);
+        universe.fill_rect(2, 1, 9, 14, CellType::Water);
+        universe.tick_n(300);
+        let level = |cols: std::ops::Range<u32>| {
+            (0..39).find(|&row| cols.clone().any(|col| universe.get_cell(row, col) == CellType::Water)).unwrap()
+        };
+        let (left, right) = (level(1..10), level(11..20));
+        assert!((left as i64 - right as i64).abs() <= 1, "left level at row {}, right at {}", left, right);
+    }
+
+    #[test]
+    fn neighborhood_radius_is_clamped() {
+        let mut universe = Universe::new_seeded(4, 3, 39);
+        universe.set_cell(2, 3, CellType::Rock);
+        let ids = universe.neighborhood(0, 0, u32::MAX);
+        assert_eq!(ids.len(), 9 * 9);
+        assert_eq!(ids[(4 + 2) * 9 + 4 + 3], CellType::Rock as u8);
+        assert_eq!(ids[0], OUT_OF_BOUNDS);
+    }
+
+    #[test]
+    fn reactions_ignore_scan_order() {
+        let react = |col: u32, tick_parity: bool| {
+            let mut universe = Universe::new_seeded(8, 1, 40);
+            universe.add_reaction(CellType::Rock, CellType::Ice, CellType::Sand, CellType::Water, 1.0);
+            universe.set_surface_tension(1.0); // Keeps the water from spreading off after reacting
+            universe.set_cell(0, col, CellType::Rock);
+            universe.set_cell(0, col + 1, CellType::Ice);
+            universe.tick_parity = tick_parity;
+            universe.tick();
+            (universe.get_cell(0, col), universe.get_cell(0, col + 1))
+        };
+        let products = (CellType::Sand, CellType::Water);
+        for tick_parity in [false, true] {
+            assert_eq!(react(0, tick_parity), products);
+            assert_eq!(react(6, tick_parity), products);
+        }
+    }
+
+    #[test]
+    fn slime_falls_with_gravity() {
+        let mut universe = Universe::new_seeded(12, 6, 41);
+        universe.set_gravity(GravityDir::Right);
+        universe.fill_rect(2, 2, 2, 2, CellType::Slime);
+        universe.tick_n(20);
+        assert_eq!(universe.count_cells(CellType::Slime), 4);
+        for row in 0..6 {
+            for col in 0..10 {
+                assert_ne!(universe.get_cell(row, col), CellType::Slime, "slime left at ({}, {})", row, col);
+            }
+        }
+    }
+
+    #[test]
+    fn nitro_goes_off_across_the_wrapped_edge() {
+        let mut universe = Universe::new_seeded(5, 16, 42);
+        universe.set_wrap(true);
+        universe.fill_rect(0, 0, 5, 1, CellType::Nitro);
+        universe.fill_rect(1, 0, 5, 1, CellType::Rock);
+        universe.set_cell(3, 2, CellType::Sand);
+        universe.tick_n(20);
+        assert!(universe.count_cells(CellType::Nitro) < 5);
+    }
+
+    #[test]
+    fn inspect_reports_updated_flag_and_temperature() {
+        let mut universe = Universe::new_seeded(6, 6, 43);
+        universe.set_cell(5, 2, CellType::Rock);
+        universe.add_heat(5, 2, 100.0);
+        let inspection = universe.inspect(5, 2).unwrap();
+        assert_eq!(inspection.cell.id(), CellType::Rock);
+        assert!(!inspection.was_updated);
+        assert_eq!(inspection.temperature, AMBIENT_TEMPERATURE + 100.0);
+        assert!(universe.inspect(6, 2).is_none());
+
+        let idx = universe.get_index(5, 2);
+        universe.mark_updated(idx);
+        assert!(universe.inspect(5, 2).unwrap().was_updated);
+        universe.tick();
+        assert!(!universe.inspect(5, 2).unwrap().was_updated);
+    }
+
+    #[cfg(feature = "scripted-rng")]
+    #[test]
+    fn scripted_rng_forces_the_slide_direction() {
+        let slide = |values: &[u32]| {
+            let mut universe = Universe::new_seeded(7, 5, 44);
+            universe.set_cell(4, 3, CellType::Rock);
+            universe.set_cell(3, 3, CellType::Sand);
+            universe.set_rng_sequence(values);
+            universe.tick();
+            (universe.get_cell(4, 2), universe.get_cell(4, 4))
+        };
+        for _ in 0..4 {
+            assert_eq!(slide(&[0]), (CellType::Dead, CellType::Sand));
+            assert_eq!(slide(&[u32::MAX]), (CellType::Sand, CellType::Dead));
+        }
+    }
+
+    #[cfg(feature = "conservation-check")]
+    #[test]
+    fn conservation_holds_across_ticks() {
+        let mut universe = Universe::new_seeded(24, 24, 45);
+        universe.fill_rect(2, 4, 8, 4, CellType::Sand);
+        universe.fill_rect(2, 14, 6, 6, CellType::Water);
+        universe.fill_rect(20, 0, 24, 1, CellType::Rock);
+        for _ in 0..60 {
+            universe.tick();
+            assert!(universe.verify_conservation());
+        }
+        assert_eq!(universe.conserved_amounts()[CellType::Sand as usize], 32);
+    }
+
+    #[cfg(feature = "particle-ids")]
+    #[test]
+    fn particle_ids_follow_moving_cells() {
+        let mut universe = Universe::new_seeded(6, 12, 46);
+        universe.set_cell(0, 1, CellType::Sand);
+        universe.set_cell(0, 4, CellType::Rock);
+        universe.tick();
+        let ids = universe.particle_ids();
+        let rock = ids[universe.get_index(0, 4)];
+        let sand = ids[universe.get_index(1, 1)];
+        assert!(sand != 0 && rock != 0 && sand != rock);
+        assert_eq!(ids[universe.get_index(0, 1)], 0);
+
+        universe.tick_n(20);
+        let ids = universe.particle_ids();
+        assert_eq!(universe.get_cell(11, 1), CellType::Sand);
+        assert_eq!(ids[universe.get_index(11, 1)], sand);
+        assert_eq!(ids[universe.get_index(0, 4)], rock);
+    }
+
+    #[test]
+    fn stamped_text_sinks_with_density_aware_paint() {
+        let mut universe = Universe::new_seeded(8, 10, 47);
+        universe.fill_rect(0, 0, 8, 10, CellType::Water);
+        universe.set_density_aware_paint(true);
+        universe.stamp_text(0, 0, "I", CellType::Sand);
+        assert_eq!(universe.count_cells(CellType::Sand), 9);
+        for col in 0..3 {
+            assert_eq!(universe.get_cell(9, col), CellType::Sand);
+            assert_eq!(universe.get_cell(0, col), CellType::Water);
+        }
+    }
+
+    #[test]
+    fn lightning_sets_flammables_alight() {
+        let mut universe = Universe::new_seeded(9, 12, 48);
+        universe.set_lightning_jaggedness(0.0);
+        universe.set_lightning_branching(0.0);
+        universe.fill_rect(8, 0, 9, 4, CellType::Oil);
+        universe.fill_rect(8, 6, 3, 4, CellType::Sand);
+        universe.strike_lightning(2);
+        universe.strike_lightning(7);
+        assert_eq!(universe.get_cell(8, 2), CellType::Fire);
+        assert_eq!(universe.count_cells(CellType::Oil), 6 * 4 - 1);
+        assert_eq!(universe.get_cell(8, 7), CellType::Sand);
+        assert_eq!(universe.count_cells(CellType::Fire), 1);
+    }
+
+    #[test]
+    fn draw_line_is_symmetric_and_cut_off() {
+        let mut universe = Universe::new_seeded(9, 6, 49);
+        universe.draw_line(0, 0, 5, 3, CellType::Rock);
+        universe.draw_line(0, 8, 5, 5, CellType::Rock);
+        for row in 0..6 {
+            for col in 0..9 {
+                assert_eq!(universe.get_cell(row, col), universe.get_cell(row, 8 - col));
+            }
+            assert_eq!((0..9).filter(|&col| universe.get_cell(row, col) == CellType::Rock).count(), 2);
+        }
+        universe.draw_line(3, 2, 3, 40, CellType::Sand);
+        assert_eq!(universe.count_cells(CellType::Sand), 7);
+    }
+
+    #[test]
+    fn sand_sinks_slower_through_tar_than_water() {
+        let ticks_to_sink = |liquid: CellType| {
+            let mut universe = Universe::new_seeded(1, 14, 50);
+            universe.fill_rect(2, 0, 1, 12, liquid);
+            universe.set_cell(0, 0, CellType::Sand);
+            (1..=1000).find(|_| {
+                universe.tick();
+                universe.get_cell(13, 0) == CellType::Sand
+            })
+        };
+        let through_water = ticks_to_sink(CellType::Water).unwrap();
+        let through_tar = ticks_to_sink(CellType::Tar).unwrap();
+        assert!(through_tar > 4 * through_water, "{} ticks through tar, {} through water", through_tar, through_water);
+    }
+
+    #[test]
+    fn collapsing_pillar_slumps_as_a_group() {
+        let mut universe = Universe::new_seeded(21, 22, 51);
+        universe.set_cell(21, 10, CellType::Rock);
+        universe.fill_rect(5, 10, 1, 16, CellType::Sand);
+        for tick in 1..=6 {
+            universe.tick();
+            // The whole column moves down every tick instead of waiting for the grains
+            // below it to trickle away one at a time
+            let column: Vec<_> = (0..21).map(|row| universe.get_cell(row, 10)).collect();
+            let top = column.iter().position(|&ct| ct == CellType::Sand).unwrap();
+            assert_eq!(top, 5 + tick);
+            assert!(column[top..].iter().all(|&ct| ct == CellType::Sand));
+        }
+    }
+}
@@ -7,7 +7,8 @@ macro_rules! log {
         web_sys::console::log_1(&format!( $( $t )* ).into());
     }
 }
-pub(crate) use log; 
+#[allow(unused_imports)]
+pub(crate) use log;
 
 pub fn set_panic_hook() {
   // When the `console_error_panic_hook` feature is enabled, we can call the
@@ -21,6 +22,85 @@ pub fn set_panic_hook() {
 }
 
 
+/// Xorshift64* generator every universe draws its random numbers from, so a universe
+/// built with the same seed and given the same inputs always plays out the same way.
+/// With the `scripted-rng` feature a sequence set with `set_sequence` takes over.
+pub struct Rng {
+  state: std::cell::Cell<u64>, // Lets `&self` methods draw numbers, never 0
+  #[cfg(feature = "scripted-rng")]
+  script: std::cell::RefCell<(Vec<u32>, usize)>, // Values to return and the next one's index
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Rng {
+    // Xorshift never leaves 0, so that seed is swapped for another one
+    let state = if seed == 0 { 0x2545_F491_4F6C_DD1D } else { seed };
+    Rng {
+      state: std::cell::Cell::new(state),
+      #[cfg(feature = "scripted-rng")]
+      script: std::cell::RefCell::new((Vec::new(), 0)),
+    }
+  }
+
+  /// A random number in [0, 1)
+  pub fn next_f64(&self) -> f64 {
+    #[cfg(feature = "scripted-rng")]
+    {
+      if let Some(value) = self.next_scripted() {
+        return value;
+      }
+    }
+    let mut x = self.state.get();
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    self.state.set(x);
+    (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  /// Makes this generator return `values` divided by 2^32 in order, starting over after the
+  /// last one. An empty sequence goes back to real random numbers.
+  #[cfg(feature = "scripted-rng")]
+  pub fn set_sequence(&self, values: &[u32]) {
+    *self.script.borrow_mut() = (values.to_vec(), 0);
+  }
+
+  #[cfg(feature = "scripted-rng")]
+  fn next_scripted(&self) -> Option<f64> {
+    let (values, next) = &mut *self.script.borrow_mut();
+    let value = *values.get(*next)?;
+    *next = (*next + 1) % values.len();
+    Some(value as f64 / (1u64 << 32) as f64)
+  }
+}
+
+/// A seed for universes that weren't given one. In the browser it comes from
+/// `Math.random`, natively from a fixed sequence so plain `cargo test` runs repeat.
+pub fn random_seed() -> u64 {
+  (source_random() * (1u64 << 53) as f64) as u64
+}
+
+#[cfg(target_arch = "wasm32")]
+fn source_random() -> f64 {
+  web_sys::js_sys::Math::random()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn source_random() -> f64 {
+  use std::cell::Cell;
+  thread_local! {
+      static STATE: Cell<u64> = const { Cell::new(0x2545_F491_4F6C_DD1D) };
+  }
+  STATE.with(|state| {
+      let mut x = state.get();
+      x ^= x << 13;
+      x ^= x >> 7;
+      x ^= x << 17;
+      state.set(x);
+      (x >> 11) as f64 / (1u64 << 53) as f64
+  })
+}
+
 use web_sys::console;
 pub struct Timer<'a> {
   name: &'a str,